@@ -0,0 +1,113 @@
+use crate::mbtile_error::{ErrorKind, MBTileError, ToMBTileResult};
+use crate::mbtiles::mbtiles_open;
+use crate::mbtiles_metadata::set_metadata_value;
+use md5::{Digest, Md5};
+use rusqlite::{Connection, OptionalExtension};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Metadata storing the checksum of the tileset.
+///
+/// Not `agg_tiles_hash`: other tools check it against the MD5 of the tile rows only.
+pub const CHECKSUM_METADATA: &str = "mbutiles_checksum";
+
+/// MD5 hex digests of the content of an MBTiles file.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Checksum {
+    /// Digest of every tile followed by the metadata, but `mbutiles_checksum`.
+    pub hash: String,
+    /// Digest of the tiles of each zoom level.
+    pub zooms: BTreeMap<u32, String>,
+}
+
+/// Feeds a value preceded by its length, so that rows can't run into each other.
+fn update_field(hasher: &mut Md5, field: &[u8]) {
+    hasher.update((field.len() as u64).to_le_bytes());
+    hasher.update(field);
+}
+
+fn compute_checksum(connection: &Connection) -> Result<Checksum, MBTileError> {
+    let mut hasher = Md5::new();
+    let mut zoom_hashers: BTreeMap<u32, Md5> = BTreeMap::new();
+    {
+        let mut statement = connection.prepare(
+            "select zoom_level, tile_column, tile_row, tile_data from tiles
+            order by zoom_level, tile_column, tile_row;",
+        )?;
+        let mut rows = statement.query(())?;
+        while let Some(row) = rows.next().desc("Can't read the tiles")? {
+            let (zoom, column, row_number): (u32, u32, u32) =
+                (row.get(0)?, row.get(1)?, row.get(2)?);
+            let data: Vec<u8> = row.get(3)?;
+            let zoom_hasher = zoom_hashers.entry(zoom).or_default();
+            for digest in [&mut hasher, zoom_hasher] {
+                digest.update(zoom.to_le_bytes());
+                digest.update(column.to_le_bytes());
+                digest.update(row_number.to_le_bytes());
+                update_field(digest, &data);
+            }
+        }
+    }
+    let mut statement =
+        connection.prepare("select name, value from metadata where name != ?1 order by name;")?;
+    let mut rows = statement.query([CHECKSUM_METADATA])?;
+    while let Some(row) = rows.next().desc("Can't read the metadata")? {
+        let (name, value): (String, String) = (row.get(0)?, row.get(1)?);
+        update_field(&mut hasher, name.as_bytes());
+        update_field(&mut hasher, value.as_bytes());
+    }
+    Ok(Checksum {
+        hash: format!("{:x}", hasher.finalize()),
+        zooms: zoom_hashers
+            .into_iter()
+            .map(|(zoom, hasher)| (zoom, format!("{:x}", hasher.finalize())))
+            .collect(),
+    })
+}
+
+/// Computes the checksum of the tiles and metadata of an MBTiles file.
+///
+/// Tiles are hashed ordered by zoom level, column and row, so the checksum only
+/// depends on the content, not on how SQLite stores it.
+pub fn checksum<P: AsRef<Path>>(input: P) -> Result<Checksum, MBTileError> {
+    let connection = mbtiles_open(input.as_ref())?;
+    compute_checksum(&connection)
+}
+
+/// Computes the checksum of an MBTiles file and stores it as `mbutiles_checksum`.
+pub fn store_checksum<P: AsRef<Path>>(input: P) -> Result<Checksum, MBTileError> {
+    let connection = mbtiles_open(input.as_ref())?;
+    let checksum = compute_checksum(&connection)?;
+    set_metadata_value(&connection, CHECKSUM_METADATA, &checksum.hash)?;
+    Ok(checksum)
+}
+
+/// Computes the checksum of an MBTiles file and compares it to `mbutiles_checksum`.
+pub fn verify_checksum<P: AsRef<Path>>(input: P) -> Result<Checksum, MBTileError> {
+    let connection = mbtiles_open(input.as_ref())?;
+    let stored: Option<String> = connection
+        .query_row(
+            "select value from metadata where name = ?1;",
+            [CHECKSUM_METADATA],
+            |row| row.get(0),
+        )
+        .optional()
+        .desc(format!("Can't read the metadata {:?}", CHECKSUM_METADATA))?;
+    let stored = stored.ok_or_else(|| {
+        MBTileError::new(
+            ErrorKind::InvalidInput,
+            format!("{:?} has no {}", input.as_ref(), CHECKSUM_METADATA),
+        )
+    })?;
+    let checksum = compute_checksum(&connection)?;
+    if !stored.eq_ignore_ascii_case(&checksum.hash) {
+        return Err(MBTileError::new(
+            ErrorKind::ChecksumMismatch,
+            format!(
+                "The checksum {} doesn't match the {} {}",
+                checksum.hash, CHECKSUM_METADATA, stored
+            ),
+        ));
+    }
+    Ok(checksum)
+}
@@ -0,0 +1,88 @@
+use crate::mbtile_error::{MBTileError, ToMBTileResult};
+use crate::mbtiles::{has_table, mbtiles_open, optimize_database};
+use crate::tile_coord::TileCoord;
+use crate::tile_filter::TileFilter;
+use crate::tile_store::TileStore;
+use rusqlite::Connection;
+use std::collections::BTreeSet;
+use std::path::Path;
+
+/// Rows removed by a deletion.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DeleteReport {
+    pub tiles: u64,
+    pub grids: u64,
+}
+
+/// Coordinates of the tiles and grids of the zoom range of `filter`.
+fn stored_coords(
+    connection: &Connection,
+    filter: &TileFilter,
+) -> Result<BTreeSet<TileCoord>, MBTileError> {
+    let (min_zoom, max_zoom) = (
+        filter.min_zoom.unwrap_or(0),
+        filter.max_zoom.unwrap_or(u32::MAX),
+    );
+    let mut coords = BTreeSet::new();
+    let mut tables = vec!["tiles"];
+    if has_table(connection, "grids")? {
+        tables.push("grids");
+    }
+    for table in tables {
+        let mut statement = connection.prepare(&format!(
+            "select zoom_level, tile_column, tile_row from {}
+            where zoom_level between ?1 and ?2;",
+            table
+        ))?;
+        let rows = statement
+            .query_map((min_zoom, max_zoom), |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()
+            .desc(format!("Can't list the {}", table))?;
+        for (zoom, x, y) in rows {
+            coords.insert(TileCoord::from_tms(zoom, x, y)?);
+        }
+    }
+    Ok(coords)
+}
+
+/// Removes the tiles selected by `filter` from an MBTiles file, with their grids
+/// and grid data, then vacuums the file if asked to.
+///
+/// A filter selecting everything removes every tile.
+pub fn delete_tiles<P: AsRef<Path>>(
+    input: P,
+    filter: &TileFilter,
+    vacuum: bool,
+) -> Result<DeleteReport, MBTileError> {
+    let mut connection = mbtiles_open(input.as_ref())?;
+    let coords: Vec<TileCoord> = match filter.tiles {
+        // looked up one by one through the index
+        Some(ref tiles) => tiles
+            .iter()
+            .filter(|coord| filter.contains(coord))
+            .cloned()
+            .collect(),
+        None => stored_coords(&connection, filter)?
+            .into_iter()
+            .filter(|coord| filter.contains(coord))
+            .collect(),
+    };
+    let mut report = DeleteReport::default();
+    let transaction = connection.transaction()?;
+    {
+        let store = TileStore::new(&transaction)?;
+        for coord in coords {
+            let (tiles, grids) = store.delete(coord)?;
+            report.tiles += tiles;
+            report.grids += grids;
+        }
+        store.remove_orphans()?;
+    }
+    transaction.commit()?;
+    if vacuum {
+        optimize_database(&connection)?;
+    }
+    Ok(report)
+}
@@ -0,0 +1,498 @@
+use crate::mbtile_error::{ErrorKind, MBTileError, ToMBTileResult};
+use crate::mbtiles::{ImageFormat, Scheme};
+use crate::tile_coord::TileCoord;
+use crate::tile_io::{Grid, Tile, TileEntry, TileSink, TileSource};
+use regex::Regex;
+use serde_json::{Map, Value};
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::path::{Component, Path, PathBuf};
+use walkdir::{DirEntry, WalkDir};
+
+fn is_visible(entry: &DirEntry) -> bool {
+    entry
+        .file_name()
+        .to_str()
+        .is_some_and(|s| !s.starts_with('.'))
+}
+
+fn get_extension(image_format: ImageFormat) -> &'static str {
+    match image_format {
+        ImageFormat::Jpg => "jpg",
+        ImageFormat::Pbf => "pbf",
+        ImageFormat::Png => "png",
+        ImageFormat::Webp => "webp",
+    }
+}
+
+/// A file of a tiles directory that couldn't be imported.
+#[derive(Debug)]
+pub struct Rejection {
+    pub path: PathBuf,
+    pub error: MBTileError,
+}
+
+/// Reads a tiles directory laid out following `scheme`.
+///
+/// Files that aren't tiles or grids are collected as rejections,
+/// unless the source is strict and aborts on the first one.
+pub struct DirectorySource {
+    input: PathBuf,
+    scheme: Scheme,
+    image_format: ImageFormat,
+    strict: bool,
+    rejected: Vec<Rejection>,
+}
+
+impl DirectorySource {
+    pub fn new<P: AsRef<Path>>(
+        input: P,
+        scheme: Scheme,
+        image_format: ImageFormat,
+    ) -> Result<DirectorySource, MBTileError> {
+        let input = input.as_ref().to_path_buf();
+        if !input.is_dir() {
+            return Err(MBTileError::new(
+                ErrorKind::InputNotFound,
+                "Can only import from a directory",
+            ));
+        }
+        if let Scheme::Wms = scheme {
+            return Err(MBTileError::new(
+                ErrorKind::UnsupportedScheme,
+                "Can't import from a wms directory",
+            ));
+        }
+        Ok(DirectorySource {
+            input,
+            scheme,
+            image_format,
+            strict: false,
+            rejected: Vec::new(),
+        })
+    }
+
+    pub fn with_strict(mut self, strict: bool) -> DirectorySource {
+        self.strict = strict;
+        self
+    }
+
+    pub fn rejected(&self) -> &[Rejection] {
+        &self.rejected
+    }
+
+    pub fn take_rejected(&mut self) -> Vec<Rejection> {
+        std::mem::take(&mut self.rejected)
+    }
+
+    fn reject(&mut self, path: &Path, error: MBTileError) -> Result<(), MBTileError> {
+        if self.strict {
+            let message = format!("Rejected {:?}: {}", path, error);
+            return Err(MBTileError {
+                kind: error.kind,
+                message: Some(message),
+                source: error.source,
+            });
+        }
+        warn!("Rejected {:?}: {}", path, error);
+        self.rejected.push(Rejection {
+            path: path.to_path_buf(),
+            error,
+        });
+        Ok(())
+    }
+
+    fn parse_entry(
+        &self,
+        end_comp: &[Component],
+        entry_path: &Path,
+    ) -> Result<TileEntry, MBTileError> {
+        let zoom = parse_zoom_dir(end_comp[0], self.scheme)?;
+        let image_dir = parse_image_dir(end_comp[1], self.scheme)?;
+        parse_filename(
+            end_comp[2],
+            self.scheme,
+            self.image_format,
+            zoom,
+            image_dir,
+            entry_path,
+        )
+    }
+}
+
+impl TileSource for DirectorySource {
+    fn read_metadata(&mut self) -> Result<Map<String, Value>, MBTileError> {
+        let metadata_path = self.input.join("metadata.json");
+        if !metadata_path.is_file() {
+            info!("metadata.json was not found");
+            return Ok(Map::new());
+        }
+        let metadata = read_metadata_json(&metadata_path)?;
+        info!("metadata.json was restored");
+        Ok(metadata)
+    }
+
+    fn visit(
+        &mut self,
+        visitor: &mut dyn FnMut(TileEntry) -> Result<(), MBTileError>,
+    ) -> Result<(), MBTileError> {
+        let base_components_length = self.input.components().count();
+        let dir_walker = WalkDir::new(&self.input)
+            .follow_links(true)
+            .min_depth(1)
+            .max_depth(3)
+            .into_iter()
+            .filter_entry(is_visible);
+        for entry_res in dir_walker {
+            let entry = entry_res.desc("invalid entry")?;
+            let entry_path = entry.path();
+            if entry_path.is_dir() {
+                // ignore directories
+                continue;
+            }
+            let end_comp: Vec<Component> = entry_path
+                .components()
+                .skip(base_components_length)
+                .collect();
+            let parsed = if end_comp.len() == 3 {
+                self.parse_entry(&end_comp, entry_path)
+            } else if entry_path == self.input.join("metadata.json") {
+                continue;
+            } else {
+                Err(MBTileError::new(
+                    ErrorKind::InvalidTilePath,
+                    "Not a tile or grid path",
+                ))
+            };
+            match parsed {
+                Ok(tile_entry) => visitor(tile_entry)?,
+                Err(err) => self.reject(entry_path, err)?,
+            }
+            info!("{}", entry.path().display());
+        }
+        Ok(())
+    }
+}
+
+fn parse_comp(component: Component) -> Result<String, MBTileError> {
+    if let Component::Normal(os_str) = component {
+        os_str
+            .to_str()
+            .ok_or_else(|| {
+                MBTileError::new(
+                    ErrorKind::InvalidTilePath,
+                    format!("Unvalid unicode path: {:?}", os_str),
+                )
+            })
+            .map(|s| s.to_owned())
+    } else {
+        Err(MBTileError::new(
+            ErrorKind::InvalidTilePath,
+            format!("Can't read path component {:?}", component),
+        ))
+    }
+}
+
+fn parse_zoom_dir(component: Component, flag_scheme: Scheme) -> Result<u32, MBTileError> {
+    let mut zoom_string = parse_comp(component)?;
+    if let Scheme::Ags = flag_scheme {
+        if !zoom_string.contains('L') {
+            warn!("You appear to be using an ags scheme on an non-arcgis Server cache.");
+        }
+        zoom_string = zoom_string.replace('L', "");
+    }
+    zoom_string
+        .parse::<u32>()
+        .desc("Can't parse component in integer format")
+        .map_err(|err| err.with_kind(ErrorKind::InvalidTilePath))
+}
+
+fn parse_image_dir(component: Component, flag_scheme: Scheme) -> Result<u32, MBTileError> {
+    let mut radix = 10u32;
+    let mut x_string = parse_comp(component)?;
+    if let Scheme::Ags = flag_scheme {
+        x_string = x_string.replace('R', "");
+        radix = 16;
+    }
+    u32::from_str_radix(x_string.as_str(), radix)
+        .desc("Can't parse component in integer format")
+        .map_err(|err| err.with_kind(ErrorKind::InvalidTilePath))
+}
+
+fn parse_filename(
+    component: Component,
+    flag_scheme: Scheme,
+    image_format: ImageFormat,
+    zoom: u32,
+    image_dir: u32,
+    entry_path: &Path,
+) -> Result<TileEntry, MBTileError> {
+    let filename = parse_comp(component)?;
+    let parts: Vec<&str> = filename.split('.').collect();
+
+    let mut radix = 10u32;
+    let mut stem_part = parts[0].to_owned();
+    if let Scheme::Ags = flag_scheme {
+        stem_part = stem_part.replace('C', "");
+        radix = 16;
+    }
+    let image_filename = u32::from_str_radix(stem_part.as_str(), radix)
+        .desc("Can't parse component in integer format")
+        .map_err(|err| err.with_kind(ErrorKind::InvalidTilePath))?;
+    // rows are validated before being flipped from TMS
+    let (coord, is_tms) = match flag_scheme {
+        Scheme::Ags => (TileCoord::new(zoom, image_filename, image_dir), false),
+        Scheme::Xyz => (TileCoord::new(zoom, image_dir, image_filename), false),
+        _ => (TileCoord::new(zoom, image_dir, image_filename), true),
+    };
+    if !coord.is_valid() {
+        return Err(MBTileError::new(
+            ErrorKind::InvalidTilePath,
+            format!("Tile {} is out of the zoom level range", coord),
+        ));
+    }
+    let coord = if is_tms {
+        TileCoord::from_tms(zoom, coord.x, coord.y)?
+    } else {
+        coord
+    };
+
+    let filtered_extension = get_extension(image_format);
+    if parts.len() == 2 && parts[1] == filtered_extension {
+        info!("Zoom: {}, Col: {}, Row {}", zoom, coord.x, coord.y);
+        Ok(TileEntry::Tile(Tile {
+            coord,
+            data: read_image(entry_path)?,
+        }))
+    } else if parts.len() == 3 && parts[1] == "grid" && parts[2] == "json" {
+        Ok(TileEntry::Grid(Grid {
+            coord,
+            utfgrid: read_grid_json(entry_path)?,
+        }))
+    } else {
+        Err(MBTileError::new(
+            ErrorKind::InvalidTilePath,
+            format!(
+                "The filtered extention {} \
+is different than the path's extention {}",
+                filtered_extension,
+                parts.get(1).unwrap_or(&"")
+            ),
+        ))
+    }
+}
+
+fn read_grid_json(grid_path: &Path) -> Result<Value, MBTileError> {
+    let mut grid_file = File::open(grid_path).desc(format!("Can't open {:?}", grid_path))?;
+    let mut grid_content = String::new();
+    grid_file
+        .read_to_string(&mut grid_content)
+        .desc(format!("Can't read file {:?}", grid_path))?;
+    let re = Regex::new(r"[\w\s=+-/]+\((\{(.|\n)*\})\);?")?;
+    grid_content = if let Some(capture) = re.captures(grid_content.as_str()) {
+        capture
+            .get(1)
+            .map(|m| m.as_str().to_owned())
+            .ok_or_else(|| MBTileError::new(ErrorKind::CorruptGrid, "Can't parse grid"))?
+    } else {
+        grid_content.clone()
+    };
+    let utfgrid = serde_json::from_str(grid_content.as_str())
+        .desc(format!("Can't parse grid {:?}", grid_path))
+        .map_err(|err| err.with_kind(ErrorKind::CorruptGrid))?;
+    Ok(utfgrid)
+}
+
+fn read_image(image_path: &Path) -> Result<Vec<u8>, MBTileError> {
+    let mut image_file = File::open(image_path).desc(format!("Can't open {:?}", image_path))?;
+    let mut buffer = Vec::new();
+    image_file
+        .read_to_end(&mut buffer)
+        .desc(format!("Can't read file {:?}", image_path))?;
+    Ok(buffer)
+}
+
+pub(crate) fn read_metadata_json(metadata_path: &Path) -> Result<Map<String, Value>, MBTileError> {
+    let mut metadata_file =
+        File::open(metadata_path).desc(format!("Can't open {:?}", metadata_path))?;
+    let mut buffer = String::new();
+    metadata_file
+        .read_to_string(&mut buffer)
+        .desc(format!("{:?} wasn't readable", metadata_path))?;
+    match serde_json::from_str(buffer.as_str()).desc(format!("Can't parse {:?}", metadata_path))? {
+        Value::Object(metadata) => Ok(metadata),
+        _ => Err(MBTileError::new(
+            ErrorKind::InvalidInput,
+            format!("{:?} is not an object", metadata_path),
+        )),
+    }
+}
+
+pub(crate) fn write_metadata_json(
+    metadata: &Map<String, Value>,
+    metadata_path: &Path,
+) -> Result<(), MBTileError> {
+    let json_str = Value::Object(metadata.clone()).to_string();
+    let mut metadata_file = File::create(metadata_path).desc("Can't create metadata file")?;
+    metadata_file
+        .write_all(json_str.as_bytes())
+        .desc("Can't write metadata file")?;
+    Ok(())
+}
+
+/// What to do with the files already in the output directory.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ExistingFiles {
+    /// Refuse to write into an existing directory.
+    #[default]
+    Fail,
+    /// Replace the files already there.
+    Overwrite,
+    /// Keep the files already there.
+    Skip,
+    /// Only rewrite the files whose content differs.
+    UpdateChanged,
+}
+
+/// Writes tiles into a directory laid out following `scheme`.
+pub struct DirectorySink {
+    output: PathBuf,
+    scheme: Scheme,
+    image_format: ImageFormat,
+    grid_callback: String,
+    existing: ExistingFiles,
+    skipped: u64,
+}
+
+impl DirectorySink {
+    /// Writes into a new `output` directory.
+    pub fn create<P: AsRef<Path>>(
+        output: P,
+        scheme: Scheme,
+        image_format: ImageFormat,
+        grid_callback: String,
+    ) -> Result<DirectorySink, MBTileError> {
+        DirectorySink::open(
+            output,
+            scheme,
+            image_format,
+            grid_callback,
+            ExistingFiles::Fail,
+        )
+    }
+
+    /// Writes into `output`, which may already exist unless `existing` is `Fail`.
+    pub fn open<P: AsRef<Path>>(
+        output: P,
+        scheme: Scheme,
+        image_format: ImageFormat,
+        grid_callback: String,
+        existing: ExistingFiles,
+    ) -> Result<DirectorySink, MBTileError> {
+        let output = output.as_ref().to_path_buf();
+        if output.exists() && existing == ExistingFiles::Fail {
+            return Err(MBTileError::new(
+                ErrorKind::OutputExists,
+                "Directory already exists",
+            ));
+        }
+        fs::create_dir_all(&output).desc("Can't create the output directory")?;
+        Ok(DirectorySink {
+            output,
+            scheme,
+            image_format,
+            grid_callback,
+            existing,
+            skipped: 0,
+        })
+    }
+
+    /// Files left as they were.
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+
+    /// Path of the file of `coord` ending with `extension`, laid out following the scheme
+    /// like `DirectorySource` reads it, its directory created.
+    fn create_tile_path(&self, coord: TileCoord, extension: &str) -> Result<PathBuf, MBTileError> {
+        if !coord.is_valid() {
+            return Err(MBTileError::new(
+                ErrorKind::InvalidInput,
+                format!("Tile coordinate {} is out of range", coord),
+            ));
+        }
+        let (z, x) = (coord.zoom, coord.x);
+        let y = match self.scheme {
+            Scheme::Xyz | Scheme::Ags => coord.y,
+            _ => coord.tms_y()?,
+        };
+        let output_path = &self.output;
+        let (tile_dir, file_name) = match self.scheme {
+            Scheme::Wms => (
+                output_path
+                    .join(format!("{:02}", z))
+                    .join(format!("{:02}", z))
+                    .join(format!("{:03}", x as i32 / 1000000))
+                    .join(format!("{:03}", (x as i32 / 1000) % 1000))
+                    .join(format!("{:02}", x as i32 % 1000))
+                    .join(format!("{:02}", y as i32 / 1000000))
+                    .join(format!("{:02}", (y as i32 / 1000) % 1000)),
+                format!("{:03}.{}", y as i32 % 1000, extension),
+            ),
+            // ArcGIS Server caches are laid out by row, in hexadecimal
+            Scheme::Ags => (
+                output_path
+                    .join(format!("L{:02}", z))
+                    .join(format!("R{:08x}", y)),
+                format!("C{:08x}.{}", x, extension),
+            ),
+            Scheme::Xyz | Scheme::Tms => (
+                output_path.join(z.to_string()).join(x.to_string()),
+                format!("{}.{}", y, extension),
+            ),
+        };
+        fs::create_dir_all(&tile_dir)
+            .desc(format!("Can't create the tile directory: {:?}", tile_dir))?;
+        Ok(tile_dir.join(file_name))
+    }
+
+    /// Writes `content` to `path`, unless the file there is kept following `existing`.
+    fn write_file(&mut self, path: &Path, content: &[u8]) -> Result<(), MBTileError> {
+        let unchanged = match self.existing {
+            ExistingFiles::Fail | ExistingFiles::Overwrite => false,
+            ExistingFiles::Skip => path.exists(),
+            // the size is compared first not to read most changed files
+            ExistingFiles::UpdateChanged => {
+                fs::metadata(path).is_ok_and(|metadata| metadata.len() == content.len() as u64)
+                    && fs::read(path).is_ok_and(|current| current == content)
+            }
+        };
+        if unchanged {
+            self.skipped += 1;
+            return Ok(());
+        }
+        fs::write(path, content).desc(format!("Can't write {:?}", path))
+    }
+}
+
+impl TileSink for DirectorySink {
+    fn write_metadata(&mut self, metadata: &Map<String, Value>) -> Result<(), MBTileError> {
+        let json_str = Value::Object(metadata.clone()).to_string();
+        self.write_file(&self.output.join("metadata.json"), json_str.as_bytes())
+    }
+
+    fn write_tile(&mut self, tile: &Tile) -> Result<(), MBTileError> {
+        let tile_path = self.create_tile_path(tile.coord, get_extension(self.image_format))?;
+        self.write_file(&tile_path, &tile.data)
+    }
+
+    fn write_grid(&mut self, grid: &Grid) -> Result<(), MBTileError> {
+        let grid_file_path = self.create_tile_path(grid.coord, "grid.json")?;
+        let grid_json = serde_json::to_string(&grid.utfgrid)?;
+        let dump = match self.grid_callback.as_str() {
+            "" | "false" | "null" => grid_json,
+            callback => format!("{}({});", callback, grid_json),
+        };
+        self.write_file(&grid_file_path, dump.as_bytes())
+    }
+}
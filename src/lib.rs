@@ -1,7 +1,7 @@
 extern crate regex;
 extern crate rusqlite;
 extern crate walkdir;
-#[macro_use(info, debug, error, warn)]
+#[macro_use(info, debug, warn)]
 extern crate log;
 extern crate flate2;
 extern crate serde;
@@ -10,6 +10,57 @@ extern crate thiserror;
 
 #[macro_use]
 mod mbtile_error;
+mod checksum;
+mod delete;
+mod directory;
 mod mbtiles;
+mod mbtiles_metadata;
+mod mbtiles_reader;
+mod mbtiles_writer;
+mod ndjson;
+mod overviews;
+mod prune;
+mod raster;
+mod recompress;
+mod tile_coord;
+mod tile_filter;
+mod tile_hash;
+mod tile_io;
+mod tile_store;
+mod tilejson;
+mod utfgrid;
+mod vector_tile;
 
-pub use crate::mbtiles::{export, import, metadata, ImageFormat, Scheme};
+pub use crate::checksum::{checksum, store_checksum, verify_checksum, Checksum, CHECKSUM_METADATA};
+pub use crate::delete::{delete_tiles, DeleteReport};
+pub use crate::directory::{DirectorySink, DirectorySource, ExistingFiles, Rejection};
+pub use crate::mbtile_error::{ErrorKind, InnerError, MBTileError, MBTypeResult};
+pub use crate::mbtiles::{
+    copy_mbtiles, copy_mbtiles_with_progress, export, export_with_progress, import,
+    import_with_options, import_with_progress, metadata, ExportOptions, ImageFormat, ImportOptions,
+    ImportReport, Scheme,
+};
+pub use crate::mbtiles_metadata::{metadata_delete, metadata_get, metadata_import, metadata_set};
+pub use crate::mbtiles_reader::MBTilesReader;
+pub use crate::mbtiles_writer::MBTilesWriter;
+pub use crate::ndjson::{ExchangeFormat, NdjsonSink, NdjsonSource};
+pub use crate::overviews::{build_overviews, OverviewsReport};
+pub use crate::prune::{prune, PruneMode, PruneReport};
+pub use crate::recompress::{
+    recompress, recompress_with_progress, RecompressOptions, RecompressReport,
+};
+pub use crate::tile_coord::{
+    flip_y, lng_lat_to_tile, tile_to_lng_lat, LngLatBounds, TileCoord, TileRange, TileRangeIter,
+    TmsCoord,
+};
+pub use crate::tile_filter::{read_tile_list, FilteredSource, TileFilter};
+pub use crate::tile_hash::{verify, HashAlgorithm, VerifyReport};
+pub use crate::tile_io::{
+    copy, copy_with_progress, CopyStats, Grid, Progress, Tile, TileEntry, TileSink, TileSource,
+};
+pub use crate::tile_store::{tile_get, tile_put};
+pub use crate::tilejson::tilejson;
+pub use crate::utfgrid::{repair_utfgrid, validate_utfgrid, GridMode, GridReport, InvalidGrid};
+pub use crate::vector_tile::{
+    decode_vector_tile, GeometryType, PropertyValue, VectorFeature, VectorLayer, VectorLayersStats,
+};
@@ -1,29 +1,40 @@
 extern crate docopt;
-extern crate regex;
-extern crate rusqlite;
-extern crate walkdir;
-#[macro_use(info, debug, error, warn)]
+extern crate indicatif;
+#[macro_use(info)]
 extern crate log;
-extern crate flate2;
+extern crate mbutiles;
 extern crate serde;
-extern crate serde_json;
-extern crate thiserror;
 
-use crate::mbtiles::{export, import, metadata, Command, ImageFormat, Scheme};
 use docopt::Docopt;
+use indicatif::{HumanBytes, ProgressBar, ProgressDrawTarget, ProgressStyle};
 use log::LevelFilter;
+use mbutiles::{
+    build_overviews, checksum, copy_mbtiles_with_progress, delete_tiles, export_with_progress,
+    import_with_progress, metadata, metadata_delete, metadata_get, metadata_import, metadata_set,
+    prune, read_tile_list, recompress_with_progress, store_checksum, tile_get, tile_put, tilejson,
+    verify, verify_checksum, ErrorKind, ExchangeFormat, ExistingFiles, ExportOptions, GridMode,
+    GridReport, HashAlgorithm, ImageFormat, ImportOptions, LngLatBounds, MBTileError, Progress,
+    PruneMode, PruneReport, RecompressOptions, Scheme, TileCoord, TileFilter,
+};
 use serde::Deserialize;
 use simplelog::{ColorChoice, Config, TermLogger, TerminalMode};
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fs;
+use std::io::{self, Write};
 use std::path::Path;
-
-#[macro_use]
-mod mbtile_error;
-mod mbtiles;
+use std::process;
 
 const USAGE: &str = "
 MBTiles utils.
 
 Usage:
+    mbutiles metadata (get | delete) [options] <input> <key>
+    mbutiles metadata set [options] <input> <key> <value>
+    mbutiles metadata import [options] <input> <metadata-json>
+    mbutiles tile get [options] <input> <tile>
+    mbutiles tile put [options] <input> <tile> <image>
+    mbutiles build-overviews [options] <input>
     mbutiles <command> [options] <input> \
                              [<output>]
     mbutiles -h | --help
@@ -36,27 +47,140 @@ Options:
   --scheme=<scheme>           Tiling scheme of the tiles. Default is \"xyz\" (z/x/y),\
  other options are \"tms\" which is also z/x/y but uses a flipped y coordinate,\
  and \"wms\" which replicates the MapServer WMS TileCache directory structure\
- \"z/000/000/x/000/000/y.png\". [default: xyz]
+ \"z/000/000/x/000/000/y.png\", exported only, or \"ags\" for ArcGIS Server caches\
+ \"Lzz/Rrrrrrrrr/Ccccccccc.png\" with hexadecimal rows and columns. Grids are laid out\
+ like the tiles. [default: xyz]
   --image-format=<format>     The format of the image tiles, either png, jpg, webp or pbf.\
  [default: png]
+  --format=<format>           Layout of the tiles exported or imported: \"directory\" or\
+ \"ndjson\", one JSON object per line, written to stdout or read from stdin when <output>\
+ or <input> is \"-\". [default: directory]
   --grid-callback=<callback>  Option to control JSONP callback for UTFGrid tiles.\
  If grids are not used as JSONP, you can remove callbacks specifying --grid_callback=\"\".\
  [default: grid]
+  --strict                    Abort the import on the first file which is not a tile or a grid,\
+ or on the first grid left out by --grids.
+  --grids=<mode>              UTFGrids which aren't valid: \"keep\" them, \"reject\" them or\
+ \"repair\" them, rejecting those which can't be. [default: keep]
+  --min-zoom=<zoom>           Lowest zoom level to build, copy, export or delete. [default: 0]
+  --max-zoom=<zoom>           Highest zoom level to copy, export or delete.
+  --bbox=<bbox>               Copy, export or delete the tiles intersecting\
+ \"west,south,east,north\".
+  --tiles-from=<file>         Copy, export or delete the tiles of a file listing \"z/x/y\"\
+ coordinates, one per line, with rows numbered following --scheme.
+  --prune=<mode>              Tiles to remove: \"empty\" (transparent or without features),\
+ \"solid\" (also of a single color), or \"dedupe\" (empty ones, and the same data for the\
+ tiles of a color, stored once: import writes the deduplicated schema of mbutil, and\
+ prune only dedupes files with that schema). The default of prune is \"empty\".
+  --overwrite                 Export into an existing directory, rewriting its files.
+  --skip-existing             Export into an existing directory, keeping its files.
+  --update-changed            Export into an existing directory, only rewriting the files\
+ whose content differs.
+  --vacuum                    Vacuum the MBTiles file after deleting tiles.
+  --quality=<quality>         JPEG quality of the recompressed tiles, from 1 to 100. [default: 85]
+  --tile-hashes=<algorithm>   Store the \"md5\" or \"xxh3\" hash of each imported tile,\
+ checked by verify.
+  --per-zoom                  Also print the checksum of each zoom level.
+  --store                     Store the checksum as the mbutiles_checksum metadata.
+  --verify                    Compare the checksum to the mbutiles_checksum metadata.
+  -o --out=<file>             Write the tile of tile get to <file> instead of stdout.
+  --tiles-url=<url>           URL template of the tiles for TileJSON, like\
+ \"https://example.com/{z}/{x}/{y}.png\".
 
  Commands:
     import
     export
-    metadata
+    metadata                  Dump the metadata to <output>/metadata.json.
+    metadata get              Print the value of the metadata <key>.
+    metadata set              Set the metadata <key> to <value>.
+    metadata delete           Remove the metadata <key>.
+    metadata import           Set every metadata of a metadata.json file.
+    tile get                  Write the tile <tile> \"z/x/y\", numbered following --scheme.
+    tile put                  Replace the tile <tile> \"z/x/y\" by the file <image>.
+    checksum                  Print the MD5 checksum of the tiles, ordered by z/x/y, and of the\
+ metadata.
+    verify                    Check the tiles against the hashes stored by --tile-hashes.
+    build-overviews           Build the missing tiles down to --min-zoom from their children.
+    copy                      Copy the tiles selected by --min-zoom, --max-zoom, --bbox and\
+ --tiles-from into the new MBTiles file <output>.
+    delete                    Remove the tiles selected by --min-zoom, --max-zoom, --bbox and\
+ --tiles-from, with their grids.
+    prune                     Remove the tiles following --prune, in place.
+    recompress                Re-encode the tiles to --image-format into <output>.
+    tilejson                  Write the metadata as TileJSON 3.0 to <output>, or to stdout.
     version
+
+ Exit status:
+    0   success
+    1   other error
+    2   invalid argument or value
+    3   input not found
+    4   output already exists
+    5   invalid tile path
+    6   unsupported scheme
+    7   missing MBTiles table
+    8   corrupt UTFGrid
+    9   IO error
+    10  SQLite error
+    11  JSON error
+    12  corrupt tile
+    13  checksum mismatch
 ";
 
+#[derive(Deserialize, Debug, Clone, Copy)]
+enum Command {
+    Import,
+    Export,
+    Metadata,
+    Tilejson,
+    BuildOverviews,
+    Recompress,
+    Prune,
+    Copy,
+    Delete,
+    Tile,
+    Checksum,
+    Verify,
+}
+
 #[derive(Deserialize, Debug)]
 struct Args {
-    arg_command: Command,
+    arg_command: Option<Command>,
+    cmd_get: bool,
+    cmd_set: bool,
+    cmd_delete: bool,
+    cmd_import: bool,
+    cmd_build_overviews: bool,
+    cmd_tile: bool,
+    cmd_put: bool,
+    arg_key: String,
+    arg_value: String,
+    arg_metadata_json: String,
+    arg_tile: String,
+    arg_image: String,
     flag_verbose: bool,
     flag_scheme: Scheme,
     flag_image_format: ImageFormat,
     flag_grid_callback: String,
+    flag_strict: bool,
+    flag_grids: GridMode,
+    flag_min_zoom: u32,
+    flag_max_zoom: Option<u32>,
+    flag_bbox: Option<String>,
+    flag_tiles_from: Option<String>,
+    flag_quality: u8,
+    flag_vacuum: bool,
+    flag_overwrite: bool,
+    flag_skip_existing: bool,
+    flag_update_changed: bool,
+    flag_prune: Option<PruneMode>,
+    flag_tile_hashes: Option<HashAlgorithm>,
+    flag_format: ExchangeFormat,
+    flag_tiles_url: Option<String>,
+    flag_out: Option<String>,
+    flag_per_zoom: bool,
+    flag_store: bool,
+    flag_verify: bool,
     arg_input: String,
     arg_output: Option<String>,
 }
@@ -64,7 +188,7 @@ struct Args {
 fn main() {
     let args: Args = Docopt::new(USAGE)
         .and_then(|d| d.version(Some("mbutiles 0.1.0".to_string())).deserialize())
-        .unwrap_or_else(|e| e.exit());
+        .unwrap_or_else(|e| exit_usage(e));
     TermLogger::init(
         if args.flag_verbose {
             LevelFilter::Info
@@ -72,46 +196,440 @@ fn main() {
             LevelFilter::Error
         },
         Config::default(),
-        TerminalMode::Mixed,
+        // keeps stdout for the tiles written there
+        if args.flag_format == ExchangeFormat::Ndjson || args.cmd_tile {
+            TerminalMode::Stderr
+        } else {
+            TerminalMode::Mixed
+        },
         ColorChoice::Auto,
     )
     .unwrap();
     info!("{:?}", args);
-    match args.arg_command {
+    // only drawn by the commands showing their progress
+    let bar = ProgressBar::hidden();
+    // <command> is empty for the usages starting with a literal command
+    let command = args.arg_command.unwrap_or(if args.cmd_build_overviews {
+        Command::BuildOverviews
+    } else if args.cmd_tile {
+        Command::Tile
+    } else {
+        Command::Metadata
+    });
+    let result = match command {
+        Command::Metadata if args.cmd_get => {
+            metadata_get(&args.arg_input, &args.arg_key).and_then(|value| match value {
+                Some(value) => {
+                    println!("{}", value);
+                    Ok(())
+                }
+                None => Err(MBTileError::new(
+                    ErrorKind::InvalidInput,
+                    format!("No metadata named {:?}", args.arg_key),
+                )),
+            })
+        }
+        Command::Metadata if args.cmd_set => {
+            metadata_set(&args.arg_input, &args.arg_key, &args.arg_value)
+        }
+        Command::Metadata if args.cmd_delete => metadata_delete(&args.arg_input, &args.arg_key)
+            .map(|deleted| {
+                if !deleted {
+                    info!("No metadata named {:?}", args.arg_key);
+                }
+            }),
+        Command::Metadata if args.cmd_import => {
+            metadata_import(&args.arg_input, &args.arg_metadata_json)
+        }
+        Command::Tile if args.cmd_get => tile_coord(&args)
+            .and_then(|coord| tile_get(&args.arg_input, coord))
+            .and_then(|data| match data {
+                Some(data) => match args.flag_out {
+                    Some(ref out) => fs::write(out, &data).map_err(|err| {
+                        MBTileError::new(ErrorKind::Io, format!("Can't write {}: {}", out, err))
+                    }),
+                    None => io::stdout()
+                        .write_all(&data)
+                        .map_err(|err| MBTileError::new(ErrorKind::Io, err.to_string())),
+                },
+                None => Err(MBTileError::new(
+                    ErrorKind::InvalidInput,
+                    format!("No tile {}", args.arg_tile),
+                )),
+            }),
+        Command::Tile if args.cmd_put => tile_coord(&args).and_then(|coord| {
+            let data = fs::read(&args.arg_image).map_err(|err| {
+                MBTileError::new(
+                    ErrorKind::InputNotFound,
+                    format!("Can't read {}: {}", args.arg_image, err),
+                )
+            })?;
+            tile_put(&args.arg_input, coord, &data)
+        }),
+        Command::Tile => Err(MBTileError::new(
+            ErrorKind::InvalidInput,
+            "tile needs get or put",
+        )),
+        Command::Import if args.arg_input == "-" && args.arg_output.is_none() => {
+            Err(MBTileError::new(
+                ErrorKind::InvalidInput,
+                "import from stdin needs an <output>",
+            ))
+        }
         Command::Import => {
             // import tiles dir into mbtiles
             let input = args.arg_input.clone();
             let output = args
                 .arg_output
                 .unwrap_or_else(|| format!("{}.mbtiles", input));
-            if let Err(err) = import(
+            let options = ImportOptions {
+                scheme: args.flag_scheme,
+                image_format: args.flag_image_format,
+                strict: args.flag_strict,
+                prune: args.flag_prune,
+                tile_hashes: args.flag_tile_hashes,
+                format: args.flag_format,
+                grids: args.flag_grids,
+            };
+            import_with_progress(
                 &Path::new(&args.arg_input),
                 &Path::new(&output),
-                args.flag_scheme,
-                args.flag_image_format,
-            ) {
-                error!("{:?}", err);
-            }
+                &options,
+                &mut show_progress(&bar),
+            )
+            .map(|report| {
+                info!("{} tiles and {} grids imported", report.tiles, report.grids);
+                for rejection in report.rejected.iter() {
+                    eprintln!(
+                        "mbutiles: rejected {}: {}",
+                        rejection.path.display(),
+                        error_summary(&rejection.error)
+                    );
+                }
+                print_grid_report(&report.checked_grids);
+                print_prune_report(&report.pruned);
+            })
         }
         Command::Export =>
         // export mbtiles to a dir
         {
-            if let Err(err) = export(
-                args.arg_input,
-                args.arg_output,
-                args.flag_scheme,
-                args.flag_image_format,
-                args.flag_grid_callback,
-            ) {
-                error!("{:?}", err);
-            }
+            existing_files(&args).and_then(|existing| {
+                let options = ExportOptions {
+                    scheme: args.flag_scheme,
+                    image_format: args.flag_image_format,
+                    grid_callback: args.flag_grid_callback.clone(),
+                    existing,
+                    format: args.flag_format,
+                };
+                export_with_progress(
+                    &args.arg_input,
+                    args.arg_output.as_ref(),
+                    &options,
+                    &tile_filter(&args)?,
+                    &mut show_progress(&bar),
+                )
+            })
         }
         Command::Metadata =>
         // dumps metadata
         {
-            if let Err(err) = metadata(args.arg_input, args.arg_output) {
-                error!("{:?}", err);
+            metadata(args.arg_input, args.arg_output)
+        }
+        Command::BuildOverviews => {
+            build_overviews(&args.arg_input, args.flag_min_zoom).map(|report| {
+                for (zoom, tiles) in report.created.iter() {
+                    info!("{} tiles built at zoom {}", tiles, zoom);
+                }
+            })
+        }
+        Command::Copy => match args.arg_output {
+            Some(ref output) => tile_filter(&args).and_then(|filter| {
+                copy_mbtiles_with_progress(
+                    Path::new(&args.arg_input),
+                    Path::new(output),
+                    &filter,
+                    &mut show_progress(&bar),
+                )
+                .map(|stats| info!("{} tiles and {} grids copied", stats.tiles, stats.grids))
+            }),
+            None => Err(MBTileError::new(
+                ErrorKind::InvalidInput,
+                "copy needs an <output>",
+            )),
+        },
+        Command::Delete => tile_filter(&args).and_then(|filter| {
+            if filter.selects_all() {
+                return Err(MBTileError::new(
+                    ErrorKind::InvalidInput,
+                    "delete needs --min-zoom, --max-zoom, --bbox or --tiles-from",
+                ));
+            }
+            delete_tiles(&args.arg_input, &filter, args.flag_vacuum)
+                .map(|report| info!("{} tiles and {} grids deleted", report.tiles, report.grids))
+        }),
+        Command::Prune => prune(&args.arg_input, args.flag_prune.unwrap_or(PruneMode::Empty))
+            .map(|report| print_prune_report(&report)),
+        Command::Recompress => match args.arg_output {
+            Some(ref output) => {
+                let options = RecompressOptions {
+                    image_format: args.flag_image_format,
+                    quality: args.flag_quality,
+                };
+                recompress_with_progress(
+                    &Path::new(&args.arg_input),
+                    &Path::new(output),
+                    &options,
+                    &mut show_progress(&bar),
+                )
+                .map(|report| {
+                    info!(
+                        "{} tiles recompressed, {} kept, {} bytes before, {} bytes after",
+                        report.recompressed, report.kept, report.bytes_before, report.bytes_after
+                    );
+                })
             }
+            None => Err(MBTileError::new(
+                ErrorKind::InvalidInput,
+                "recompress needs an <output>",
+            )),
+        },
+        Command::Checksum => if args.flag_store && args.flag_verify {
+            Err(MBTileError::new(
+                ErrorKind::InvalidInput,
+                "checksum can't --store and --verify at once",
+            ))
+        } else if args.flag_store {
+            store_checksum(&args.arg_input)
+        } else if args.flag_verify {
+            verify_checksum(&args.arg_input)
+        } else {
+            checksum(&args.arg_input)
         }
+        .map(|checksum| {
+            println!("{}", checksum.hash);
+            if args.flag_per_zoom {
+                for (zoom, hash) in checksum.zooms.iter() {
+                    println!("zoom {}: {}", zoom, hash);
+                }
+            }
+        }),
+        Command::Verify => verify(&args.arg_input).and_then(|report| {
+            info!("{} tiles verified", report.tiles);
+            for coord in report.mismatched.iter() {
+                eprintln!("mbutiles: tile {} doesn't match its hash", coord);
+            }
+            for coord in report.unhashed.iter() {
+                eprintln!("mbutiles: tile {} has no hash", coord);
+            }
+            for coord in report.missing.iter() {
+                eprintln!("mbutiles: tile {} is missing", coord);
+            }
+            if report.is_ok() {
+                Ok(())
+            } else {
+                Err(MBTileError::new(
+                    ErrorKind::ChecksumMismatch,
+                    format!(
+                        "{} tiles don't match their hash, {} have none, {} are missing",
+                        report.mismatched.len(),
+                        report.unhashed.len(),
+                        report.missing.len()
+                    ),
+                ))
+            }
+        }),
+        Command::Tilejson => match args.flag_tiles_url {
+            Some(ref tiles_url) => tilejson(&args.arg_input, tiles_url, args.flag_scheme)
+                .and_then(|document| write_output(args.arg_output, &document.to_string())),
+            None => Err(MBTileError::new(
+                ErrorKind::InvalidInput,
+                "tilejson needs --tiles-url",
+            )),
+        },
+    };
+    bar.finish();
+    if let Err(err) = result {
+        eprintln!("mbutiles: {}", error_summary(&err));
+        process::exit(exit_code(err.kind));
+    }
+}
+
+/// The `Usage:` section of `USAGE`.
+fn usage_summary() -> &'static str {
+    let start = USAGE.find("Usage:").unwrap_or(0);
+    let usage = &USAGE[start..];
+    usage[..usage.find("\n\n").unwrap_or(usage.len())].trim_end()
+}
+
+/// Exits like `docopt::Error::exit`, but with the status of an invalid argument.
+fn exit_usage(err: docopt::Error) -> ! {
+    if !err.fatal() {
+        err.exit();
+    }
+    match err {
+        // already followed by the usage
+        docopt::Error::WithProgramUsage(..) => eprintln!("{}", err),
+        _ => eprintln!("{}\n\n{}", err, usage_summary()),
+    }
+    process::exit(exit_code(ErrorKind::InvalidInput))
+}
+
+/// The coordinate of <tile>, whose row is numbered following --scheme.
+fn tile_coord(args: &Args) -> Result<TileCoord, MBTileError> {
+    let coord: TileCoord = args.arg_tile.parse()?;
+    match args.flag_scheme {
+        Scheme::Xyz => Ok(coord),
+        Scheme::Tms => TileCoord::from_tms(coord.zoom, coord.x, coord.y),
+        scheme => Err(MBTileError::new(
+            ErrorKind::UnsupportedScheme,
+            format!("tile doesn't support the {} scheme", scheme),
+        )),
+    }
+}
+
+/// What export does with the files of an existing output directory.
+fn existing_files(args: &Args) -> Result<ExistingFiles, MBTileError> {
+    match (
+        args.flag_overwrite,
+        args.flag_skip_existing,
+        args.flag_update_changed,
+    ) {
+        (false, false, false) => Ok(ExistingFiles::Fail),
+        (true, false, false) => Ok(ExistingFiles::Overwrite),
+        (false, true, false) => Ok(ExistingFiles::Skip),
+        (false, false, true) => Ok(ExistingFiles::UpdateChanged),
+        _ => Err(MBTileError::new(
+            ErrorKind::InvalidInput,
+            "Only one of --overwrite, --skip-existing and --update-changed can be given",
+        )),
+    }
+}
+
+/// The tiles selected by --min-zoom, --max-zoom, --bbox and --tiles-from.
+fn tile_filter(args: &Args) -> Result<TileFilter, MBTileError> {
+    let mut filter =
+        TileFilter::new().with_zoom_range(Some(args.flag_min_zoom), args.flag_max_zoom);
+    if let Some(ref bbox) = args.flag_bbox {
+        filter = filter.with_bounds(bbox.parse::<LngLatBounds>()?);
+    }
+    if let Some(ref tiles_from) = args.flag_tiles_from {
+        filter = filter.with_tiles(read_tile_list(tiles_from, args.flag_scheme)?);
+    }
+    Ok(filter)
+}
+
+/// Prints the grids which aren't valid and the keys without data.
+fn print_grid_report(report: &GridReport) {
+    for invalid in report.invalid.iter() {
+        let action = if report.rejected.contains(&invalid.coord) {
+            "rejected"
+        } else if report.repaired.contains(&invalid.coord) {
+            "repaired"
+        } else {
+            "invalid"
+        };
+        eprintln!(
+            "mbutiles: {} grid {}: {}",
+            action,
+            invalid.coord,
+            error_summary(&invalid.error)
+        );
+    }
+    for (coord, keys) in report.missing_data.iter() {
+        eprintln!(
+            "mbutiles: grid {} has no data for the keys {}",
+            coord,
+            keys.join(", ")
+        );
+    }
+}
+
+/// Prints the tiles removed and deduplicated per zoom.
+fn print_prune_report(report: &PruneReport) {
+    let zooms: BTreeSet<&u32> = report
+        .removed
+        .keys()
+        .chain(report.deduplicated.keys())
+        .collect();
+    for zoom in zooms {
+        println!(
+            "zoom {}: {} removed, {} deduplicated",
+            zoom,
+            report.removed.get(zoom).unwrap_or(&0),
+            report.deduplicated.get(zoom).unwrap_or(&0)
+        );
+    }
+}
+
+/// Writes `content` to the `output` file, or to stdout.
+fn write_output(output: Option<String>, content: &str) -> Result<(), MBTileError> {
+    match output {
+        Some(output) => fs::write(&output, content).map_err(|err| {
+            MBTileError::new(ErrorKind::Io, format!("Can't write {}: {}", output, err))
+        }),
+        None => {
+            println!("{}", content);
+            Ok(())
+        }
+    }
+}
+
+/// Updates `bar` with the tiles processed, the bytes written and the throughput.
+fn show_progress(bar: &ProgressBar) -> impl FnMut(&Progress) + '_ {
+    bar.set_draw_target(ProgressDrawTarget::stderr());
+    bar.set_style(
+        ProgressStyle::with_template(
+            "{spinner} [{elapsed_precise}] {pos} tiles and grids ({per_sec}) {msg}",
+        )
+        .unwrap(),
+    );
+    move |progress| {
+        if let Some(total) = progress.total {
+            if bar.length() != Some(total) {
+                bar.set_length(total);
+                bar.set_style(
+                    ProgressStyle::with_template(
+                        "[{elapsed_precise}] {wide_bar} {pos}/{len} tiles and grids \
+                         ({per_sec}, ETA {eta}) {msg}",
+                    )
+                    .unwrap(),
+                );
+            }
+        }
+        bar.set_position(progress.processed());
+        bar.set_message(format!(
+            "{} written, {}/s",
+            HumanBytes(progress.stats.bytes),
+            HumanBytes(progress.bytes_per_second() as u64)
+        ));
+    }
+}
+
+/// The error message followed by the messages of its sources.
+fn error_summary(err: &MBTileError) -> String {
+    let mut summary = err.to_string();
+    let mut source = err.source();
+    while let Some(cause) = source {
+        summary.push_str(&format!(": {}", cause));
+        source = cause.source();
+    }
+    summary
+}
+
+fn exit_code(kind: ErrorKind) -> i32 {
+    match kind {
+        ErrorKind::Other => 1,
+        ErrorKind::InvalidInput => 2,
+        ErrorKind::InputNotFound => 3,
+        ErrorKind::OutputExists => 4,
+        ErrorKind::InvalidTilePath => 5,
+        ErrorKind::UnsupportedScheme => 6,
+        ErrorKind::SchemaMissing => 7,
+        ErrorKind::CorruptGrid => 8,
+        ErrorKind::Io => 9,
+        ErrorKind::Sqlite => 10,
+        ErrorKind::Json => 11,
+        ErrorKind::CorruptTile => 12,
+        ErrorKind::ChecksumMismatch => 13,
     }
 }
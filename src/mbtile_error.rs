@@ -1,3 +1,4 @@
+use std::error::Error;
 use std::{convert, io};
 
 use std::num;
@@ -28,39 +29,116 @@ pub enum InnerError {
     Utf8Error(#[from] str::Utf8Error),
     #[error("regex error: {0}")]
     Regex(#[from] regex::Error),
+    #[error("Image error: {0}")]
+    Image(#[from] image::ImageError),
+    #[error("{0}")]
+    MBTile(Box<MBTileError>),
+}
+
+impl InnerError {
+    fn kind(&self) -> ErrorKind {
+        match *self {
+            InnerError::None | InnerError::Regex(_) => ErrorKind::Other,
+            InnerError::IO(_) | InnerError::WalkDir(_) | InnerError::DataError(_) => ErrorKind::Io,
+            InnerError::Rusqlite(_) => ErrorKind::Sqlite,
+            InnerError::ParseInt(_) | InnerError::Utf8Error(_) => ErrorKind::InvalidInput,
+            InnerError::SerdeError(_) => ErrorKind::Json,
+            InnerError::Image(ref err) => match *err {
+                image::ImageError::Decoding(_) | image::ImageError::Unsupported(_) => {
+                    ErrorKind::CorruptTile
+                }
+                image::ImageError::IoError(_) => ErrorKind::Io,
+                _ => ErrorKind::Other,
+            },
+            InnerError::MBTile(ref err) => err.kind,
+        }
+    }
+
+    fn as_error(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            InnerError::None => None,
+            InnerError::IO(ref err) => Some(err),
+            InnerError::Rusqlite(ref err) => Some(err),
+            InnerError::ParseInt(ref err) => Some(err),
+            InnerError::WalkDir(ref err) => Some(err),
+            InnerError::SerdeError(ref err) => Some(err),
+            InnerError::DataError(ref err) => Some(err),
+            InnerError::Utf8Error(ref err) => Some(err),
+            InnerError::Regex(ref err) => Some(err),
+            InnerError::Image(ref err) => Some(err),
+            InnerError::MBTile(ref err) => Some(err.as_ref()),
+        }
+    }
+}
+
+/// What went wrong, to match on without parsing messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    /// The output file or directory is already there.
+    OutputExists,
+    /// The input file or directory can't be found or has the wrong type.
+    InputNotFound,
+    /// A path of a tiles directory doesn't match the tiling scheme.
+    InvalidTilePath,
+    /// The MBTiles file lacks a table (`tiles`, `metadata`...).
+    SchemaMissing,
+    /// The tiling scheme can't be used for this operation.
+    UnsupportedScheme,
+    /// A UTFGrid can't be parsed or isn't valid.
+    CorruptGrid,
+    /// A raster tile can't be decoded.
+    CorruptTile,
+    /// The content doesn't match its stored checksum.
+    ChecksumMismatch,
+    /// A user supplied value can't be parsed.
+    InvalidInput,
+    Io,
+    Sqlite,
+    Json,
+    Other,
 }
 
 #[derive(Debug)]
 pub struct MBTileError {
+    pub kind: ErrorKind,
     pub message: Option<String>,
     pub source: InnerError,
 }
 
 impl MBTileError {
-    pub fn new_static(message: &'static str) -> MBTileError {
+    pub fn new<S: Into<String>>(kind: ErrorKind, message: S) -> MBTileError {
         MBTileError {
-            message: Some(message.to_owned()),
+            kind,
+            message: Some(message.into()),
             source: InnerError::None,
         }
     }
 
-    pub fn new(message: String) -> MBTileError {
-        MBTileError {
-            message: Some(message),
-            source: InnerError::None,
-        }
+    pub fn with_kind(mut self, kind: ErrorKind) -> MBTileError {
+        self.kind = kind;
+        self
     }
 }
 
 impl Display for MBTileError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self.message {
-            Some(ref msg) => write!(f, "{:?}{}", msg, self.source),
+            Some(ref msg) => write!(f, "{}", msg),
             None => write!(f, "{}", self.source),
         }
     }
 }
 
+impl Error for MBTileError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self.message {
+            Some(_) => self.source.as_error(),
+            // the inner error is already displayed
+            None => self.source.as_error().and_then(|err| err.source()),
+        }
+    }
+}
+
 // impl Debug for MBTileError {
 //     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 //         write!(f, "{}", self)
@@ -78,12 +156,14 @@ macro_rules! to_MBTileResult {
         $(
             impl<U> ToMBTileResult<U, $p> for Result<U, $p>     {
                 fn desc<S: Into<String>>(self, description: S) -> MBTypeResult<U> {
-                    self.map_err(|err|
+                    self.map_err(|err| {
+                        let source: InnerError = convert::From::from(err);
                         MBTileError {
+                            kind: source.kind(),
                             message: Some(description.into()),
-                            source: convert::From::from(err)
+                            source,
                         }
-                    )
+                    })
                 }
             }
         )*
@@ -99,15 +179,28 @@ to_MBTileResult!(
     flate2::CompressError,
     str::Utf8Error,
     regex::Error,
+    image::ImageError,
 );
 
+impl<U> ToMBTileResult<U, MBTileError> for Result<U, MBTileError> {
+    fn desc<S: Into<String>>(self, description: S) -> MBTypeResult<U> {
+        self.map_err(|err| MBTileError {
+            kind: err.kind,
+            message: Some(description.into()),
+            source: InnerError::MBTile(Box::new(err)),
+        })
+    }
+}
+
 macro_rules! MBTileError_from_Error {
     ($source_error:ty) => {
         impl convert::From<$source_error> for MBTileError {
-            fn from(kind: $source_error) -> MBTileError {
+            fn from(err: $source_error) -> MBTileError {
+                let source: InnerError = convert::From::from(err);
                 MBTileError {
+                    kind: source.kind(),
                     message: None,
-                    source: convert::From::from(kind),
+                    source,
                 }
             }
         }
@@ -120,6 +213,7 @@ MBTileError_from_Error!(flate2::CompressError);
 MBTileError_from_Error!(serde_json::Error);
 MBTileError_from_Error!(str::Utf8Error);
 MBTileError_from_Error!(regex::Error);
+MBTileError_from_Error!(image::ImageError);
 
 // macro_rules! InnerError_from_Error {
 //     ($source_error:ty, $selector:ident) => (
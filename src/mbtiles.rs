@@ -1,26 +1,21 @@
-use crate::mbtile_error::{MBTileError, ToMBTileResult};
-use flate2::read::ZlibDecoder;
-use flate2::write::ZlibEncoder;
-use flate2::Compression;
-use regex::Regex;
-use rusqlite::{Connection, Row};
+use crate::directory::{
+    write_metadata_json, DirectorySink, DirectorySource, ExistingFiles, Rejection,
+};
+use crate::mbtile_error::{ErrorKind, MBTileError, ToMBTileResult};
+use crate::mbtiles_reader::MBTilesReader;
+use crate::mbtiles_writer::MBTilesWriter;
+use crate::ndjson::{create_output, open_input, ExchangeFormat, NdjsonSink, NdjsonSource};
+use crate::prune::{PruneMode, PruneReport, PruneSink, Pruner};
+use crate::tile_filter::{ExtentSink, FilteredSource, TileFilter};
+use crate::tile_hash::HashAlgorithm;
+use crate::tile_io::{copy_with_progress, CopyStats, Progress, TileSink, TileSource};
+use crate::utfgrid::{GridCheckSink, GridChecker, GridMode, GridReport};
+use crate::vector_tile::VectorLayersSink;
+use rusqlite::Connection;
 use serde::Deserialize;
-use serde_json::{Map, Value};
-use std::collections::HashMap;
 use std::fmt;
-use std::fs::{self, File};
-use std::io::prelude::*;
-use std::io::Cursor;
-use std::iter::Iterator;
-use std::path::{Component, Path, PathBuf};
-use walkdir::{DirEntry, WalkDir};
-
-#[derive(Deserialize, Debug)]
-pub enum Command {
-    Import,
-    Export,
-    Metadata,
-}
+use std::fs;
+use std::path::{Path, PathBuf};
 
 #[derive(Deserialize, Debug, Clone, Copy)]
 pub enum Scheme {
@@ -42,7 +37,8 @@ impl fmt::Display for Scheme {
     }
 }
 
-#[derive(Deserialize, Debug, Clone, Copy)]
+/// Encoding of the tiles, displayed as the value of the `format` metadata.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ImageFormat {
     Png,
     Jpg,
@@ -50,11 +46,54 @@ pub enum ImageFormat {
     Pbf,
 }
 
-fn mbtiles_connect(mbtiles_file: &Path) -> Result<Connection, MBTileError> {
+impl fmt::Display for ImageFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let res = match *self {
+            ImageFormat::Png => "png",
+            ImageFormat::Jpg => "jpg",
+            ImageFormat::Webp => "webp",
+            ImageFormat::Pbf => "pbf",
+        };
+        write!(f, "{}", res)
+    }
+}
+
+pub(crate) fn mbtiles_connect(mbtiles_file: &Path) -> Result<Connection, MBTileError> {
     Connection::open(mbtiles_file).desc(format!("Can't connect to {:?}", mbtiles_file))
 }
 
-fn optimize_connection(connection: &Connection) -> Result<(), MBTileError> {
+/// Connects to an existing MBTiles file, which has `tiles` and `metadata` tables.
+pub(crate) fn mbtiles_open(mbtiles_file: &Path) -> Result<Connection, MBTileError> {
+    if !mbtiles_file.is_file() {
+        return Err(MBTileError::new(
+            ErrorKind::InputNotFound,
+            format!("Can't find the MBTiles file {:?}", mbtiles_file),
+        ));
+    }
+    let connection = mbtiles_connect(mbtiles_file)?;
+    for table in ["tiles", "metadata"] {
+        if !has_table(&connection, table)? {
+            return Err(MBTileError::new(
+                ErrorKind::SchemaMissing,
+                format!("{:?} has no {} table", mbtiles_file, table),
+            ));
+        }
+    }
+    Ok(connection)
+}
+
+pub(crate) fn has_table(connection: &Connection, table: &str) -> Result<bool, MBTileError> {
+    let count = connection
+        .query_row(
+            "SELECT count(*) FROM sqlite_master WHERE (type='table' OR type='view') AND name=?1;",
+            [table],
+            |row| row.get::<usize, i32>(0),
+        )
+        .desc(format!("Can't check if the table {} exists", table))?;
+    Ok(count != 0)
+}
+
+pub(crate) fn optimize_connection(connection: &Connection) -> Result<(), MBTileError> {
     connection
         .execute_batch(
             "
@@ -66,7 +105,7 @@ fn optimize_connection(connection: &Connection) -> Result<(), MBTileError> {
         .desc("Cannot execute sqlite optimization query")
 }
 
-fn optimize_database(connection: &Connection) -> Result<(), MBTileError> {
+pub(crate) fn optimize_database(connection: &Connection) -> Result<(), MBTileError> {
     info!("SQLite analyse");
     connection
         .execute_batch("ANALYZE;")
@@ -78,7 +117,7 @@ fn optimize_database(connection: &Connection) -> Result<(), MBTileError> {
     Ok(())
 }
 
-fn mbtiles_setup(connection: &Connection) -> Result<(), MBTileError> {
+pub(crate) fn mbtiles_setup(connection: &Connection) -> Result<(), MBTileError> {
     connection
         .execute_batch(
             "
@@ -101,315 +140,193 @@ fn mbtiles_setup(connection: &Connection) -> Result<(), MBTileError> {
         .desc("Can't create schema")
 }
 
-fn is_visible(entry: &DirEntry) -> bool {
-    entry
-        .file_name()
-        .to_str()
-        .map_or(false, |s| !s.starts_with('.'))
-}
-
-fn get_extension(image_format: ImageFormat) -> &'static str {
-    match image_format {
-        ImageFormat::Jpg => "jpg",
-        ImageFormat::Pbf => "pbf",
-        ImageFormat::Png => "png",
-        ImageFormat::Webp => "webp",
-    }
-}
-
-fn insert_metadata(input: &PathBuf, connection: &Connection) -> Result<(), MBTileError> {
-    if input.is_file() {
-        info!("metadata.json was not found");
-        return Ok(());
-    }
-    let mut metadata_file = File::open(input.join("metadata.json"))
-        .desc(format!("Can't open metadata.json: {:?}", input))?;
-    let mut buffer = String::new();
-    metadata_file
-        .read_to_string(&mut buffer)
-        .desc("metadata.json wasn't readable")?;
-    // TODO: use try! add error type
-    let data: HashMap<String, String> = serde_json::from_str(buffer.as_str())?;
-    // if data.is_object() {
-    //     let obj = data.as_object()
-    //         .ok_or_else(|| MBTileError::new_static("metadata is not an object"))?;
-    for (key, value) in data.iter() {
-        // let value_str = value.as_string().ok_or_else(|| MBTileError::new_static("metadata object has a non string value"))?;
-        connection
-            .execute(
-                "insert into metadata (name, value) values (?1, ?2)",
-                (key, &value),
-            )
-            .desc("Can't insert medata in database")?;
-    }
-    // }
-    info!("metadata.json was restored");
-    Ok(())
-}
-
-pub fn import<P: AsRef<Path>>(
-    input: P,
-    output: P,
-    flag_scheme: Scheme,
-    flag_image_format: ImageFormat,
-) -> Result<(), MBTileError> {
-    info!("Importing disk to MBTiles");
-    let input_path: PathBuf = input.as_ref().to_path_buf();
-    let output_path: PathBuf = output.as_ref().to_path_buf();
-    debug!("{:?} --> {:?}", &input_path, &output_path);
-    if !input_path.is_dir() {
-        return Err(MBTileError::new_static("Can only import from a directory"));
-    }
-    let connection = mbtiles_connect(&output_path)?;
-    optimize_connection(&connection)?;
-    mbtiles_setup(&connection)?;
-    insert_metadata(&input_path, &connection)?;
-    walk_dir_image(&input_path, flag_scheme, flag_image_format, &connection)?;
-    debug!("tiles (and grids) inserted.");
-    optimize_database(&connection)?;
-    Ok(())
+/// Creates the deduplicated schema of mbutil: each distinct tile or grid is stored once,
+/// and `tiles`, `grids` and `grid_data` are views over the `map` of the coordinates.
+pub(crate) fn mbtiles_setup_deduplicated(connection: &Connection) -> Result<(), MBTileError> {
+    connection
+        .execute_batch(
+            "
+        CREATE TABLE map (zoom_level INTEGER, tile_column INTEGER,
+            tile_row INTEGER, tile_id TEXT, grid_id TEXT);
+        CREATE TABLE images (tile_data BLOB, tile_id TEXT);
+        CREATE TABLE grid_utfgrid (grid_id TEXT, grid_utfgrid BLOB);
+        CREATE TABLE grid_key (grid_id TEXT, key_name TEXT);
+        CREATE TABLE keymap (key_name TEXT, key_json TEXT);
+        CREATE TABLE metadata
+            (name TEXT, value TEXT);
+        CREATE UNIQUE INDEX map_index ON map (zoom_level, tile_column, tile_row);
+        CREATE UNIQUE INDEX images_id ON images (tile_id);
+        CREATE UNIQUE INDEX grid_utfgrid_lookup ON grid_utfgrid (grid_id);
+        CREATE UNIQUE INDEX grid_key_lookup ON grid_key (grid_id, key_name);
+        CREATE UNIQUE INDEX keymap_lookup ON keymap (key_name);
+        CREATE UNIQUE INDEX name ON metadata (name);
+        CREATE VIEW tiles AS
+            SELECT map.zoom_level AS zoom_level, map.tile_column AS tile_column,
+                map.tile_row AS tile_row, images.tile_data AS tile_data
+            FROM map JOIN images ON images.tile_id = map.tile_id;
+        CREATE VIEW grids AS
+            SELECT map.zoom_level AS zoom_level, map.tile_column AS tile_column,
+                map.tile_row AS tile_row, grid_utfgrid.grid_utfgrid AS grid
+            FROM map JOIN grid_utfgrid ON grid_utfgrid.grid_id = map.grid_id;
+        CREATE VIEW grid_data AS
+            SELECT map.zoom_level AS zoom_level, map.tile_column AS tile_column,
+                map.tile_row AS tile_row, keymap.key_name AS key_name,
+                keymap.key_json AS key_json
+            FROM map JOIN grid_key ON map.grid_id = grid_key.grid_id
+            JOIN keymap ON grid_key.key_name = keymap.key_name;
+    ",
+        )
+        .desc("Can't create the deduplicated schema")
 }
 
-fn flip_y(zoom: u32, y: u32) -> u32 {
-    2u32.pow(zoom) - 1 - y
+#[derive(Debug, Clone, Copy)]
+pub struct ImportOptions {
+    pub scheme: Scheme,
+    pub image_format: ImageFormat,
+    /// Abort on the first rejected file instead of reporting it.
+    pub strict: bool,
+    /// Tiles left out of the MBTiles file.
+    pub prune: Option<PruneMode>,
+    /// Hash stored for each tile.
+    pub tile_hashes: Option<HashAlgorithm>,
+    /// Layout of the input, a directory or a stream read from stdin when `-`.
+    pub format: ExchangeFormat,
+    /// What to do with the UTFGrids which aren't valid.
+    pub grids: GridMode,
 }
 
-fn walk_dir_image(
-    input: &Path,
-    flag_scheme: Scheme,
-    flag_image_format: ImageFormat,
-    connection: &Connection,
-) -> Result<(), MBTileError> {
-    let base_components_length = input.components().count();
-    let dir_walker = WalkDir::new(input)
-        .follow_links(true)
-        .min_depth(1)
-        .max_depth(3)
-        .into_iter()
-        .filter_entry(is_visible);
-    for entry_res in dir_walker {
-        let entry = entry_res.desc("invalid entry")?;
-        let entry_path = entry.path();
-        if entry_path.is_dir() {
-            // ignore directories
-            continue;
-        }
-        let end_comp: Vec<Component> = entry_path
-            .components()
-            .skip(base_components_length)
-            .collect();
-        if end_comp.len() == 3 {
-            parse_zoom_dir(end_comp[0], flag_scheme)
-                .and_then(|zoom| {
-                    parse_image_dir(end_comp[1], flag_scheme).and_then(|image_dir| {
-                        parse_filename_and_insert(
-                            end_comp[2],
-                            flag_scheme,
-                            flag_image_format,
-                            zoom,
-                            image_dir,
-                            entry_path,
-                            connection,
-                        )
-                    })
-                })
-                .unwrap_or_else(|err| error!("{}", err))
+impl Default for ImportOptions {
+    fn default() -> ImportOptions {
+        ImportOptions {
+            scheme: Scheme::Xyz,
+            image_format: ImageFormat::Png,
+            strict: false,
+            prune: None,
+            tile_hashes: None,
+            format: ExchangeFormat::Directory,
+            grids: GridMode::Keep,
         }
-        info!("{}", entry.path().display());
     }
-    Ok(())
 }
 
-fn parse_comp(component: Component) -> Result<String, MBTileError> {
-    if let Component::Normal(os_str) = component {
-        os_str
-            .to_str()
-            .ok_or_else(|| MBTileError::new(format!("Unvalid unicode path: {:?}", os_str)))
-            .map(|s| s.to_owned())
-    } else {
-        Err(MBTileError::new(format!(
-            "Can't read path component {:?}",
-            component
-        )))
-    }
+#[derive(Debug, Clone)]
+pub struct ExportOptions {
+    pub scheme: Scheme,
+    pub image_format: ImageFormat,
+    /// JSONP callback wrapping the UTFGrids, none if empty, "false" or "null".
+    pub grid_callback: String,
+    /// What to do with the files of an existing output directory.
+    pub existing: ExistingFiles,
+    /// Layout of the output, a directory or a stream written to stdout when `-`.
+    pub format: ExchangeFormat,
 }
 
-fn parse_zoom_dir(component: Component, flag_scheme: Scheme) -> Result<u32, MBTileError> {
-    let mut zoom_string = parse_comp(component)?;
-    if let Scheme::Ags = flag_scheme {
-        if !zoom_string.contains('L') {
-            warn!("You appear to be using an ags scheme on an non-arcgis Server cache.");
+impl Default for ExportOptions {
+    fn default() -> ExportOptions {
+        ExportOptions {
+            scheme: Scheme::Xyz,
+            image_format: ImageFormat::Png,
+            grid_callback: "grid".to_owned(),
+            existing: ExistingFiles::Fail,
+            format: ExchangeFormat::Directory,
         }
-        zoom_string = zoom_string.replace('L', "");
     }
-    zoom_string
-        .parse::<u32>()
-        .desc("Can't parse component in integer format")
 }
 
-fn parse_image_dir(component: Component, flag_scheme: Scheme) -> Result<u32, MBTileError> {
-    let mut radix = 10u32;
-    let mut x_string = parse_comp(component)?;
-    if let Scheme::Ags = flag_scheme {
-        x_string = x_string.replace('R', "");
-        radix = 16;
-    }
-    u32::from_str_radix(x_string.as_str(), radix).desc("Can't parse component in integer format")
+/// What an import inserted and which files it skipped.
+#[derive(Debug)]
+pub struct ImportReport {
+    pub tiles: u64,
+    pub grids: u64,
+    pub rejected: Vec<Rejection>,
+    pub pruned: PruneReport,
+    pub checked_grids: GridReport,
 }
 
-fn parse_filename_and_insert(
-    component: Component,
+pub fn import<P: AsRef<Path>>(
+    input: P,
+    output: P,
     flag_scheme: Scheme,
-    image_format: ImageFormat,
-    zoom: u32,
-    image_dir: u32,
-    entry_path: &Path,
-    connection: &Connection,
+    flag_image_format: ImageFormat,
 ) -> Result<(), MBTileError> {
-    let filename = parse_comp(component)?;
-    let parts: Vec<&str> = filename.split('.').collect();
-
-    let mut radix = 10u32;
-    let mut stem_part = parts[0].to_owned();
-    if let Scheme::Ags = flag_scheme {
-        stem_part = stem_part.replace('C', "");
-        radix = 16;
-    }
-    let image_filename = u32::from_str_radix(stem_part.as_str(), radix)
-        .desc("Can't parse component in integer format")?;
-    let (col, row) = match flag_scheme {
-        Scheme::Ags => (image_filename, flip_y(zoom, image_dir)),
-        Scheme::Xyz => (image_dir, flip_y(zoom, image_filename)),
-        _ => (image_dir, image_filename),
+    let options = ImportOptions {
+        scheme: flag_scheme,
+        image_format: flag_image_format,
+        ..ImportOptions::default()
     };
+    import_with_options(input, output, &options).map(|_| ())
+}
 
-    let filtered_extension = get_extension(image_format);
-    if parts.len() == 2 && parts[1] == filtered_extension {
-        info!("Zoom: {}, Col: {}, Row {}", zoom, col, row);
-        insert_image_sqlite(entry_path, zoom, col, row, connection)
-    } else if parts.len() == 3 && parts[1] == "grid" && parts[2] == "json" {
-        insert_grid_json(entry_path, zoom, col, row, connection)
-    } else {
-        Err(MBTileError::new(format!(
-            "The filtered extention {} \
-is different than the path's extention {}",
-            filtered_extension, parts[1]
-        )))
-    }
+/// Like `import` following `options`, reporting what was imported and left out.
+pub fn import_with_options<P: AsRef<Path>>(
+    input: P,
+    output: P,
+    options: &ImportOptions,
+) -> Result<ImportReport, MBTileError> {
+    import_with_progress(input, output, options, &mut |_| {})
 }
 
-fn insert_grid_json(
-    grid_path: &Path,
-    zoom: u32,
-    column: u32,
-    row: u32,
-    connection: &Connection,
-) -> Result<(), MBTileError> {
-    let mut grid_file = File::open(grid_path).desc(format!("Can't open {:?}", grid_path))?;
-    let mut grid_content = String::new();
-    grid_file
-        .read_to_string(&mut grid_content)
-        .desc(format!("Can't read file {:?}", grid_path))?;
-    let re = Regex::new(r"[\w\s=+-/]+\((\{(.|\n)*\})\);?")?;
-    grid_content = if let Some(capture) = re.captures(grid_content.as_str()) {
-        capture
-            .get(1)
-            .map(|m| m.as_str().to_owned())
-            .ok_or_else(|| MBTileError::new_static("Can't parse grid"))?
-    } else {
-        grid_content.clone()
+pub fn import_with_progress<P: AsRef<Path>>(
+    input: P,
+    output: P,
+    options: &ImportOptions,
+    progress: &mut dyn FnMut(&Progress),
+) -> Result<ImportReport, MBTileError> {
+    info!("Importing disk to MBTiles");
+    let input_path: PathBuf = input.as_ref().to_path_buf();
+    let output_path: PathBuf = output.as_ref().to_path_buf();
+    debug!("{:?} --> {:?}", &input_path, &output_path);
+    let mut directory_source = None;
+    let mut ndjson_source;
+    let source: &mut dyn TileSource = match options.format {
+        ExchangeFormat::Directory => directory_source.insert(
+            DirectorySource::new(&input_path, options.scheme, options.image_format)?
+                .with_strict(options.strict),
+        ),
+        ExchangeFormat::Ndjson => {
+            ndjson_source = NdjsonSource::new(open_input(&input_path)?, options.scheme)?;
+            &mut ndjson_source
+        }
     };
-    let utfgrid = serde_json::from_str(grid_content.as_str())?;
-    let (data_opt, utfgrid_obj) = if let Value::Object(mut utfgrid_obj) = utfgrid {
-        (utfgrid_obj.remove("data"), utfgrid_obj)
+    // deduplicated tiles only share their data in the deduplicated schema
+    let mut writer = if options.prune == Some(PruneMode::Dedupe) {
+        MBTilesWriter::create_deduplicated(&output_path)?
     } else {
-        return Err(MBTileError::new_static("grid json not an object"));
+        MBTilesWriter::create(&output_path)?
     };
-    let kk = Value::Object(utfgrid_obj);
-    let filtered_json_grid = serde_json::to_string(&kk)?;
-    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
-    encoder.write_all(filtered_json_grid.as_bytes())?;
-    let zipped_json = encoder.finish()?;
-    connection
-        .execute(
-            "insert into grids (zoom_level, tile_column, tile_row, grid) values (?1, ?2, ?3, ?4);",
-            (
-                &(zoom as i64),
-                &(column as i64),
-                &(row as i64),
-                &zipped_json,
-            ),
-        )
-        .desc("Can't insert zipped grid in database")?;
-    let utfgrid_obj = kk
-        .as_object()
-        .ok_or_else(|| MBTileError::new_static("grid is not an object"))?;
-    let aa = &utfgrid_obj.get("keys");
-    if let Some(Value::Array(keys_array)) = *aa {
-        let filtered_keys = keys_array.iter().filter_map(|k| {
-            k.as_str()
-                .and_then(|k| if k.is_empty() { None } else { Some(k) })
-        });
-        // let filtered_keys = try!(gg.and_then(|keys_res| keys_res.filter(|&k| k != "")));
-        for key in filtered_keys {
-            if let Some(ref data) = data_opt {
-                if let Value::Object(ref data_obj) = *data {
-                    let key_json = &data_obj[key];
-                    connection.execute("insert into grid_data (zoom_level, tile_column, tile_row, key_name, key_json) values (?1, ?2, ?3, ?4, ?5);",
-                                       (&(zoom as i64), &(column as i64), &(row as i64), &key, &key_json.to_string()))?;
-                } else {
-                    println!("Can't get some data_obj {:?}", data);
-                }
-            } else {
-                println!("Can't get some data {:?}", data_opt);
-            }
-        }
-    } else {
-        println!("Can't get some json array {:?}", aa);
+    if let Some(algorithm) = options.tile_hashes {
+        writer = writer.with_tile_hashes(algorithm)?;
     }
-    Ok(())
-}
-
-fn insert_image_sqlite(
-    image_path: &Path,
-    zoom: u32,
-    column: u32,
-    row: u32,
-    connection: &Connection,
-) -> Result<(), MBTileError> {
-    let mut image_file = File::open(image_path).desc(format!("Can't open {:?}", image_path))?;
-    let mut buffer = Vec::new();
-    image_file
-        .read_to_end(&mut buffer)
-        .desc(format!("Can't read file {:?}", image_path))?;
-    connection
-        .execute(
-            "insert into tiles (zoom_level,
-                    tile_column, tile_row, tile_data) values
-                    (?1, ?2, ?3, ?4);",
-            (&(zoom as i64), &(column as i64), &(row as i64), &buffer),
-        )
-        .desc(format!("Can't insert {:?}", image_path))?;
-    Ok(())
-}
-
-fn export_metadata(connection: &Connection, output_path: &Path) -> Result<(), MBTileError> {
-    let mut metadata_statement = connection.prepare("select name, value from metadata;")?;
-
-    let metadata_statement_rows = metadata_statement.query_map([], |row| {
-        Ok((row.get::<usize, String>(0)?, Value::String(row.get(1)?)))
-    })?;
-    let metadata_map: Map<String, Value> =
-        metadata_statement_rows.collect::<Result<Map<_, _>, rusqlite::Error>>()?;
-
-    let json_obj = Value::Object(metadata_map);
-    let json_str = json_obj.to_string();
-    let metadata_path = output_path.join("metadata.json");
-    let mut metadata_file = File::create(metadata_path).desc("Can't create metadata file")?;
-    metadata_file
-        .write(json_str.as_bytes())
-        .desc("Can't write metadata file")?;
-    Ok(())
+    let mut vector_sink;
+    let sink: &mut dyn TileSink = match options.image_format {
+        ImageFormat::Pbf => {
+            vector_sink = VectorLayersSink::new(&mut writer);
+            &mut vector_sink
+        }
+        _ => &mut writer,
+    };
+    let mut pruner = options.prune.map(Pruner::new);
+    let mut prune_sink;
+    let sink: &mut dyn TileSink = match pruner {
+        Some(ref mut pruner) => {
+            prune_sink = PruneSink::new(sink, pruner);
+            &mut prune_sink
+        }
+        None => sink,
+    };
+    let mut checker = GridChecker::new(options.grids, options.strict);
+    let mut sink = GridCheckSink::new(sink, &mut checker);
+    let stats = copy_with_progress(source, &mut sink, progress)?;
+    debug!("tiles (and grids) inserted.");
+    let pruned = pruner.map_or_else(PruneReport::default, Pruner::into_report);
+    let checked_grids = checker.into_report();
+    Ok(ImportReport {
+        tiles: stats.tiles - pruned.removed_tiles(),
+        grids: stats.grids - checked_grids.rejected.len() as u64,
+        rejected: directory_source.map_or_else(Vec::new, |mut source| source.take_rejected()),
+        pruned,
+        checked_grids,
+    })
 }
 
 pub fn export<P: AsRef<Path>>(
@@ -418,13 +335,53 @@ pub fn export<P: AsRef<Path>>(
     flag_scheme: Scheme,
     flag_image_format: ImageFormat,
     flag_grid_callback: String,
+) -> Result<(), MBTileError> {
+    let options = ExportOptions {
+        scheme: flag_scheme,
+        image_format: flag_image_format,
+        grid_callback: flag_grid_callback,
+        existing: ExistingFiles::Fail,
+        format: ExchangeFormat::Directory,
+    };
+    export_with_progress(
+        input,
+        opt_output,
+        &options,
+        &TileFilter::default(),
+        &mut |_| {},
+    )
+}
+
+/// Like `export` following `options`, only writing the tiles and grids selected
+/// by `filter`, and calling `progress` after each one.
+pub fn export_with_progress<P: AsRef<Path>>(
+    input: P,
+    opt_output: Option<P>,
+    options: &ExportOptions,
+    filter: &TileFilter,
+    progress: &mut dyn FnMut(&Progress),
 ) -> Result<(), MBTileError> {
     let input_path: PathBuf = input.as_ref().to_path_buf();
-    if !input_path.is_file() {
-        return Err(MBTileError::new(format!(
-            "Can't export from a file at path {:?}",
-            input_path
-        )));
+    let mut reader = MBTilesReader::open(&input_path)?;
+    if options.format == ExchangeFormat::Ndjson {
+        let overwrite = match options.existing {
+            ExistingFiles::Fail => false,
+            ExistingFiles::Overwrite => true,
+            ExistingFiles::Skip | ExistingFiles::UpdateChanged => {
+                return Err(MBTileError::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "{:?} only applies to directory exports, ndjson can only overwrite",
+                        options.existing
+                    ),
+                ));
+            }
+        };
+        let writer = create_output(opt_output.as_ref().map(|p| p.as_ref()), overwrite)?;
+        let mut sink = NdjsonSink::new(writer, options.scheme)?;
+        let mut source = FilteredSource::new(&mut reader, filter);
+        copy_with_progress(&mut source, &mut sink, progress)?;
+        return Ok(());
     }
     let output: PathBuf = opt_output
         .map(|p| p.as_ref().to_path_buf())
@@ -432,174 +389,70 @@ pub fn export<P: AsRef<Path>>(
             input_path.file_stem().map(PathBuf::from)
             //.map(|stem_str| stem_str.to_owned())
         })
-        .ok_or(MBTileError::new_static(
+        .ok_or(MBTileError::new(
+            ErrorKind::InvalidInput,
             "Cannot identify an output directory",
         ))?;
+    if output == Path::new("-") {
+        return Err(MBTileError::new(
+            ErrorKind::InvalidInput,
+            "Only ndjson exports can be written to stdout, use --format=ndjson",
+        ));
+    }
     debug!("Exporting MBTiles to disk");
     debug!("{:?} --> {:?}", &input_path, &output);
-    let output_path = Path::new(&output);
-    if output_path.exists() {
-        return Err(MBTileError::new_static("Directory already exists"));
-    }
-    fs::create_dir_all(output_path).desc("Can't create the output directory")?;
-    let connection = mbtiles_connect(&input_path)?;
-    export_metadata(&connection, output_path)?;
-    // TODO show pregression:
-    // let zoom_level_count = get_count(&connection, "tiles");
-
-    let mut tiles_statement =
-        connection.prepare("select zoom_level, tile_column, tile_row, tile_data from tiles;")?;
-    let mut tiles_rows = tiles_statement.query(())?;
-    while let Some(tile_res) = tiles_rows.next()? {
-        export_tile(tile_res, output_path, flag_scheme, flag_image_format)?;
+    let mut sink = DirectorySink::open(
+        &output,
+        options.scheme,
+        options.image_format,
+        options.grid_callback.clone(),
+        options.existing,
+    )?;
+    let mut source = FilteredSource::new(&mut reader, filter);
+    copy_with_progress(&mut source, &mut sink, progress)?;
+    if sink.skipped() > 0 {
+        info!("{} files already there left as they were", sink.skipped());
     }
-    export_grid(&connection, output_path, flag_scheme, flag_grid_callback)?;
     Ok(())
 }
 
-fn export_tile(
-    tile: &Row,
-    output_path: &Path,
-    flag_scheme: Scheme,
-    flag_image_format: ImageFormat,
-) -> Result<(), MBTileError> {
-    let (z, x, mut y): (u32, u32, u32) = (
-        tile.get::<usize, u32>(0)?,
-        tile.get::<usize, u32>(1)?,
-        tile.get::<usize, u32>(2)?,
-    );
-    let tile_dir = match flag_scheme {
-        Scheme::Xyz => {
-            y = flip_y(z, y);
-            output_path.join(z.to_string()).join(x.to_string())
-        }
-        Scheme::Wms => output_path
-            .join(format!("{:02}", z))
-            .join(format!("{:02}", z))
-            .join(format!("{:03}", x as i32 / 1000000))
-            .join(format!("{:03}", (x as i32 / 1000) % 1000))
-            .join(format!("{:02}", x as i32 % 1000))
-            .join(format!("{:02}", y as i32 / 1000000))
-            .join(format!("{:02}", (y as i32 / 1000) % 1000)),
-        _ => output_path.join(z.to_string()).join(x.to_string()),
-    };
-    fs::create_dir_all(&tile_dir)
-        .desc(format!("Can't create the tile directory: {:?}", tile_dir))?;
-    let tile_path = match flag_scheme {
-        Scheme::Wms => tile_dir.join(format!(
-            "{:03}.{}",
-            y as i32 % 1000,
-            get_extension(flag_image_format)
-        )),
-        _ => tile_dir.join(format!("{}.{}", y, get_extension(flag_image_format))),
-    };
-    let mut tile_file = File::create(tile_path)?;
-    tile_file.write_all(&tile.get::<usize, Vec<u8>>(3)?)?;
-    Ok(())
+/// Copies the tiles and grids of `input` selected by `filter` into a new `output`
+/// MBTiles file, with the metadata and its `bounds`, `minzoom` and `maxzoom` updated.
+pub fn copy_mbtiles<P: AsRef<Path>>(
+    input: P,
+    output: P,
+    filter: &TileFilter,
+) -> Result<CopyStats, MBTileError> {
+    copy_mbtiles_with_progress(input, output, filter, &mut |_| {})
 }
 
-// fn get_count(connection: &Connection, table: &str) -> Result<i32, MBTileError> {
-//     connection.query_row_safe("select count(zoom_level) from (?);",
-//                               &[&table],
-//                               |row| row.get::<i32, i32>(0))
-//               .desc(format!("Can't get {} zoom level", table))
-// }
-
-fn export_grid(
-    connection: &Connection,
-    output_path: &Path,
-    flag_scheme: Scheme,
-    flag_grid_callback: String,
-) -> Result<(), MBTileError> {
-    // TODO show progression:
-    // let grids_zoom_level_count = get_count(&connection, "grids");
-
-    let mut has_grids_table_statement = connection.prepare(
-        "SELECT count(*) FROM sqlite_master WHERE (type='table' OR type='view') AND name='grids';",
-    )?;
-    let has_grids_table = has_grids_table_statement
-        .query_row([], |row| row.get::<usize, i32>(0))
-        .desc("Can't check if the table grids exists")?;
-    if has_grids_table == 0 {
-        return Ok(());
-    }
-    let mut grids_statement =
-        connection.prepare("select zoom_level, tile_column, tile_row, grid from grids;")?;
-    let mut grids_rows = grids_statement.query(())?;
-    while let Some(grid_row) = grids_rows.next()? {
-        let (zoom_level, tile_column, mut y): (i32, i32, i32) =
-            (grid_row.get(0)?, grid_row.get(1)?, grid_row.get(2)?);
-        if let Scheme::Xyz = flag_scheme {
-            y = flip_y(zoom_level as u32, y as u32) as i32;
-        }
-        let grid_dir = output_path
-            .join(zoom_level.to_string())
-            .join(tile_column.to_string());
-        fs::create_dir_all(&grid_dir)
-            .desc(format!("Can't create the directory: {:?}", grid_dir))?;
-        let grid_zip = grid_row.get::<usize, Vec<u8>>(3)?;
-        let grid_cursor = Cursor::new(grid_zip);
-        let mut decoder = ZlibDecoder::new(grid_cursor);
-        let mut unzipped_grid = String::new();
-        decoder.read_to_string(&mut unzipped_grid)?;
-        let grid_json = serde_json::from_str(unzipped_grid.as_str())
-            .desc(format!("Grid json: {}", unzipped_grid))?;
-
-        let mut grid_data_statement = connection.prepare(
-            "select key_name, key_json FROM
-            grid_data WHERE
-            zoom_level = (?) and
-            tile_column = (?) and
-            tile_row = (?);",
-        )?;
-
-        let grid_data_rows =
-            grid_data_statement.query_map((zoom_level, tile_column, y), |row| {
-                let json = row.get::<usize, String>(1)?;
-                let key = row.get::<usize, String>(0)?;
-                let val = serde_json::from_str(json.as_str())
-                    .map(|res: Value| (key, res))
-                    .desc(format!("Can't parse json: {:?}", json));
-                Ok(val)
-            })?;
-        let data = grid_data_rows
-            .map(|res| res.desc("").and_then(|rr| rr))
-            .collect::<Result<Map<_, _>, MBTileError>>()?;
-
-        let grid_object = if let Value::Object(mut grid_object) = grid_json {
-            grid_object.insert("data".to_owned(), Value::Object(data));
-            grid_object
-        } else {
-            return Err(MBTileError::new_static("grid is not an object"));
-        };
-        let grid_file_path = grid_dir.join(format!("{}.grid.json", y));
-        let mut grid_file = File::create(grid_file_path)?;
-        let grid_json = serde_json::to_string(&grid_object)?;
-        let dump = match flag_grid_callback.as_str() {
-            "" | "false" | "null" => grid_json,
-            callback => format!("{}({});", callback, grid_json),
-        };
-        grid_file.write_all(dump.as_bytes())?;
-    }
-    Ok(())
+pub fn copy_mbtiles_with_progress<P: AsRef<Path>>(
+    input: P,
+    output: P,
+    filter: &TileFilter,
+    progress: &mut dyn FnMut(&Progress),
+) -> Result<CopyStats, MBTileError> {
+    debug!("{:?} --> {:?}", input.as_ref(), output.as_ref());
+    let mut reader = MBTilesReader::open(input.as_ref())?;
+    let mut writer = MBTilesWriter::create(output.as_ref())?;
+    let mut source = FilteredSource::new(&mut reader, filter);
+    let mut sink = ExtentSink::new(&mut writer, filter.bounds);
+    copy_with_progress(&mut source, &mut sink, progress)
 }
 
 pub fn metadata<P: AsRef<Path>>(input: P, opt_output: Option<P>) -> Result<(), MBTileError> {
     let input_path: PathBuf = input.as_ref().to_path_buf();
-    if !input_path.is_file() {
-        error!("Can only export from a file")
-    }
+    let mut reader = MBTilesReader::open(&input_path)?;
     let output: PathBuf = opt_output
         .map(|p| p.as_ref().to_path_buf())
         .or_else(|| {
             input_path.file_stem().map(PathBuf::from)
             //.map(|stem_str| stem_str.to_owned())
         })
-        .ok_or(MBTileError::new_static(
+        .ok_or(MBTileError::new(
+            ErrorKind::InvalidInput,
             "Cannot identify an output directory",
         ))?;
-    let output_path = output.join("metadata.json");
-    let connection = mbtiles_connect(&input_path)?;
-    export_metadata(&connection, &output_path)?;
-    Ok(())
+    fs::create_dir_all(&output).desc("Can't create the output directory")?;
+    write_metadata_json(&reader.read_metadata()?, &output.join("metadata.json"))
 }
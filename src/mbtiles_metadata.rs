@@ -0,0 +1,193 @@
+use crate::directory::read_metadata_json;
+use crate::mbtile_error::{ErrorKind, MBTileError, ToMBTileResult};
+use crate::mbtiles::mbtiles_open;
+use rusqlite::{Connection, OptionalExtension};
+use serde_json::{Map, Value};
+use std::path::Path;
+
+pub(crate) fn set_metadata_value(
+    connection: &Connection,
+    name: &str,
+    value: &str,
+) -> Result<(), MBTileError> {
+    connection
+        .execute(
+            "insert or replace into metadata (name, value) values (?1, ?2)",
+            (name, value),
+        )
+        .desc("Can't insert medata in database")?;
+    Ok(())
+}
+
+/// Members of the `json` row defined by the MBTiles spec, also decoded when stored as rows
+/// of their own.
+const JSON_ROW_KEYS: [&str; 2] = ["vector_layers", "tilestats"];
+
+/// Metadata stored as numbers by the MBTiles spec.
+const NUMBER_KEYS: [&str; 2] = ["minzoom", "maxzoom"];
+
+/// Metadata stored as comma-separated numbers by the MBTiles spec.
+const LIST_KEYS: [&str; 2] = ["bounds", "center"];
+
+/// `[-10, -10, 10, 10]` into `-10,-10,10,10`.
+fn join_numbers(key: &str, values: &[Value]) -> Result<String, MBTileError> {
+    values
+        .iter()
+        .map(|value| match value {
+            Value::Number(number) => Ok(number.to_string()),
+            _ => Err(MBTileError::new(
+                ErrorKind::InvalidInput,
+                format!("The {} metadata is not a list of numbers", key),
+            )),
+        })
+        .collect::<Result<Vec<String>, MBTileError>>()
+        .map(|numbers| numbers.join(","))
+}
+
+/// Converts JSON metadata into `metadata` table rows.
+///
+/// Strings are kept, numbers and booleans are written as text, `bounds` and `center`
+/// arrays as comma-separated numbers, other arrays and objects are merged into the `json`
+/// row.
+pub(crate) fn metadata_to_rows(
+    metadata: &Map<String, Value>,
+) -> Result<Vec<(String, String)>, MBTileError> {
+    let mut rows = Vec::new();
+    let mut json_row = Map::new();
+    for (key, value) in metadata.iter() {
+        if key == "json" {
+            let json_value = match *value {
+                Value::String(ref json_str) => serde_json::from_str(json_str)
+                    .desc("Can't parse the json metadata")
+                    .map_err(|err| err.with_kind(ErrorKind::InvalidInput))?,
+                ref json_value => json_value.clone(),
+            };
+            if let Value::Object(json_obj) = json_value {
+                json_row.extend(json_obj);
+            } else {
+                return Err(MBTileError::new(
+                    ErrorKind::InvalidInput,
+                    "The json metadata is not an object",
+                ));
+            }
+            continue;
+        }
+        let value_str = match *value {
+            Value::Null => continue,
+            Value::String(ref value_str) => value_str.clone(),
+            Value::Array(ref values) if LIST_KEYS.contains(&key.as_str()) => {
+                join_numbers(key, values)?
+            }
+            Value::Array(_) | Value::Object(_) => {
+                json_row.insert(key.clone(), value.clone());
+                continue;
+            }
+            ref other => other.to_string(),
+        };
+        rows.push((key.clone(), value_str));
+    }
+    if !json_row.is_empty() {
+        rows.push(("json".to_owned(), Value::Object(json_row).to_string()));
+    }
+    Ok(rows)
+}
+
+/// Converts `metadata` table rows into JSON metadata, reverting `metadata_to_rows`.
+///
+/// The members of the `json` row are moved to the top level, unless a row has their name.
+/// Only the `json` row and the `JSON_ROW_KEYS` are decoded, the other values stay strings
+/// even when they look like JSON.
+pub(crate) fn rows_to_metadata(rows: Vec<(String, String)>) -> Map<String, Value> {
+    let names: Vec<String> = rows.iter().map(|(key, _)| key.clone()).collect();
+    let mut metadata = Map::new();
+    for (key, value_str) in rows {
+        let value = if key == "json" {
+            match serde_json::from_str(&value_str) {
+                Ok(Value::Object(json_obj)) => {
+                    let mut kept = Map::new();
+                    for (json_key, json_value) in json_obj {
+                        if names.contains(&json_key) {
+                            kept.insert(json_key, json_value);
+                        } else {
+                            metadata.insert(json_key, json_value);
+                        }
+                    }
+                    if kept.is_empty() {
+                        continue;
+                    }
+                    Value::Object(kept)
+                }
+                _ => Value::String(value_str),
+            }
+        } else if NUMBER_KEYS.contains(&key.as_str()) {
+            value_str
+                .parse::<i64>()
+                .map(Value::from)
+                .unwrap_or(Value::String(value_str))
+        } else if JSON_ROW_KEYS.contains(&key.as_str()) {
+            serde_json::from_str(&value_str).unwrap_or(Value::String(value_str))
+        } else {
+            Value::String(value_str)
+        };
+        metadata.insert(key, value);
+    }
+    metadata
+}
+
+pub(crate) fn insert_metadata(
+    connection: &Connection,
+    metadata: &Map<String, Value>,
+) -> Result<(), MBTileError> {
+    for (key, value) in metadata_to_rows(metadata)? {
+        set_metadata_value(connection, &key, &value)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn read_metadata(connection: &Connection) -> Result<Map<String, Value>, MBTileError> {
+    let mut metadata_statement = connection.prepare("select name, value from metadata;")?;
+    let rows = metadata_statement
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<Vec<(String, String)>, rusqlite::Error>>()?;
+    Ok(rows_to_metadata(rows))
+}
+
+/// Value of the `key` metadata, if set.
+pub fn metadata_get<P: AsRef<Path>>(input: P, key: &str) -> Result<Option<String>, MBTileError> {
+    let connection = mbtiles_open(input.as_ref())?;
+    connection
+        .query_row(
+            "select value from metadata where name = ?1;",
+            [key],
+            |row| row.get(0),
+        )
+        .optional()
+        .desc(format!("Can't read the metadata {:?}", key))
+}
+
+pub fn metadata_set<P: AsRef<Path>>(input: P, key: &str, value: &str) -> Result<(), MBTileError> {
+    let connection = mbtiles_open(input.as_ref())?;
+    set_metadata_value(&connection, key, value)
+}
+
+/// Removes the `key` metadata, returns whether it was set.
+pub fn metadata_delete<P: AsRef<Path>>(input: P, key: &str) -> Result<bool, MBTileError> {
+    let connection = mbtiles_open(input.as_ref())?;
+    let deleted = connection
+        .execute("delete from metadata where name = ?1;", [key])
+        .desc(format!("Can't delete the metadata {:?}", key))?;
+    Ok(deleted > 0)
+}
+
+/// Sets every metadata of a `metadata.json` file, keeping the others.
+///
+/// The `json` row is replaced as a whole when the file has a `json`,
+/// `vector_layers` or `tilestats` member.
+pub fn metadata_import<P: AsRef<Path>, Q: AsRef<Path>>(
+    input: P,
+    metadata_json: Q,
+) -> Result<(), MBTileError> {
+    let connection = mbtiles_open(input.as_ref())?;
+    let metadata = read_metadata_json(metadata_json.as_ref())?;
+    insert_metadata(&connection, &metadata)
+}
@@ -0,0 +1,182 @@
+use crate::mbtile_error::{ErrorKind, MBTileError, ToMBTileResult};
+use crate::mbtiles::{has_table, mbtiles_open};
+use crate::mbtiles_metadata::read_metadata;
+use crate::tile_coord::{TileCoord, TmsCoord};
+use crate::tile_io::{Grid, Tile, TileEntry, TileSource};
+use flate2::read::ZlibDecoder;
+use rusqlite::{Connection, OptionalExtension};
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+use std::io::prelude::*;
+use std::io::Cursor;
+use std::path::Path;
+
+/// Reads the tiles, UTFGrids and metadata of an existing MBTiles file.
+pub struct MBTilesReader {
+    connection: Connection,
+}
+
+impl MBTilesReader {
+    pub fn open<P: AsRef<Path>>(input: P) -> Result<MBTilesReader, MBTileError> {
+        Ok(MBTilesReader {
+            connection: mbtiles_open(input.as_ref())?,
+        })
+    }
+
+    fn has_grids(&self) -> Result<bool, MBTileError> {
+        has_table(&self.connection, "grids")
+    }
+
+    fn get_count(&self, table: &str) -> Result<u64, MBTileError> {
+        self.connection
+            .query_row(&format!("select count(*) from {};", table), [], |row| {
+                row.get::<usize, u64>(0)
+            })
+            .desc(format!("Can't count {}", table))
+    }
+
+    /// The `data` of the grid stored at `storage`, keyed like its `keys`.
+    fn read_grid_data(&self, storage: TmsCoord) -> Result<Map<String, Value>, MBTileError> {
+        let mut grid_data_statement = self.connection.prepare_cached(
+            "select key_name, key_json FROM
+            grid_data WHERE
+            zoom_level = (?) and
+            tile_column = (?) and
+            tile_row = (?);",
+        )?;
+
+        let grid_data_rows =
+            grid_data_statement.query_map((storage.zoom, storage.x, storage.y), |row| {
+                let json = row.get::<usize, String>(1)?;
+                let key = row.get::<usize, String>(0)?;
+                let val = serde_json::from_str(json.as_str())
+                    .map(|res: Value| (key, res))
+                    .desc(format!("Can't parse json: {:?}", json));
+                Ok(val)
+            })?;
+        let data = grid_data_rows
+            .map(|res| res.desc("").and_then(|rr| rr))
+            .collect::<Result<Map<_, _>, MBTileError>>()?;
+        Ok(data)
+    }
+
+    /// Unzips the grid stored at `storage`, with its `data`.
+    fn read_grid(&self, storage: TmsCoord, grid_zip: Vec<u8>) -> Result<Value, MBTileError> {
+        let mut decoder = ZlibDecoder::new(Cursor::new(grid_zip));
+        let mut unzipped_grid = String::new();
+        decoder
+            .read_to_string(&mut unzipped_grid)
+            .desc(format!("Can't unzip grid {}", storage))
+            .map_err(|err| err.with_kind(ErrorKind::CorruptGrid))?;
+        let grid_json = serde_json::from_str(unzipped_grid.as_str())
+            .desc(format!("Grid json: {}", unzipped_grid))
+            .map_err(|err| err.with_kind(ErrorKind::CorruptGrid))?;
+        if let Value::Object(mut grid_object) = grid_json {
+            let data = self.read_grid_data(storage)?;
+            grid_object.insert("data".to_owned(), Value::Object(data));
+            Ok(Value::Object(grid_object))
+        } else {
+            Err(MBTileError::new(
+                ErrorKind::CorruptGrid,
+                "grid is not an object",
+            ))
+        }
+    }
+}
+
+impl TileSource for MBTilesReader {
+    fn read_metadata(&mut self) -> Result<Map<String, Value>, MBTileError> {
+        read_metadata(&self.connection)
+    }
+
+    fn count(&mut self) -> Result<Option<u64>, MBTileError> {
+        let mut count = self.get_count("tiles")?;
+        if self.has_grids()? {
+            count += self.get_count("grids")?;
+        }
+        Ok(Some(count))
+    }
+
+    fn visit(
+        &mut self,
+        visitor: &mut dyn FnMut(TileEntry) -> Result<(), MBTileError>,
+    ) -> Result<(), MBTileError> {
+        let mut tiles_statement = self
+            .connection
+            .prepare("select zoom_level, tile_column, tile_row, tile_data from tiles;")?;
+        let mut tiles_rows = tiles_statement.query(())?;
+        while let Some(tile_row) = tiles_rows.next()? {
+            visitor(TileEntry::Tile(Tile {
+                coord: TmsCoord::new(tile_row.get(0)?, tile_row.get(1)?, tile_row.get(2)?)
+                    .try_into()?,
+                data: tile_row.get(3)?,
+            }))?;
+        }
+
+        if !self.has_grids()? {
+            return Ok(());
+        }
+        let mut grids_statement = self
+            .connection
+            .prepare("select zoom_level, tile_column, tile_row, grid from grids;")?;
+        let mut grids_rows = grids_statement.query(())?;
+        while let Some(grid_row) = grids_rows.next()? {
+            let storage = TmsCoord::new(grid_row.get(0)?, grid_row.get(1)?, grid_row.get(2)?);
+            visitor(TileEntry::Grid(Grid {
+                coord: storage.try_into()?,
+                utfgrid: self.read_grid(storage, grid_row.get(3)?)?,
+            }))?;
+        }
+        Ok(())
+    }
+
+    /// Looks the tiles up by coordinates instead of scanning the whole file.
+    fn visit_tiles(
+        &mut self,
+        coords: &HashSet<TileCoord>,
+        visitor: &mut dyn FnMut(TileEntry) -> Result<(), MBTileError>,
+    ) -> Result<(), MBTileError> {
+        let mut coords: Vec<TileCoord> =
+            coords.iter().copied().filter(TileCoord::is_valid).collect();
+        coords.sort();
+        let mut tile_statement = self.connection.prepare(
+            "select tile_data from tiles
+            where zoom_level = ?1 and tile_column = ?2 and tile_row = ?3;",
+        )?;
+        for coord in coords.iter() {
+            let storage = TmsCoord::try_from(*coord)?;
+            let data: Option<Vec<u8>> = tile_statement
+                .query_row((storage.zoom, storage.x, storage.y), |row| row.get(0))
+                .optional()
+                .desc(format!("Can't read tile {}", coord))?;
+            if let Some(data) = data {
+                visitor(TileEntry::Tile(Tile {
+                    coord: *coord,
+                    data,
+                }))?;
+            }
+        }
+
+        if !self.has_grids()? {
+            return Ok(());
+        }
+        let mut grid_statement = self.connection.prepare(
+            "select grid from grids
+            where zoom_level = ?1 and tile_column = ?2 and tile_row = ?3;",
+        )?;
+        for coord in coords.iter() {
+            let storage = TmsCoord::try_from(*coord)?;
+            let grid_zip: Option<Vec<u8>> = grid_statement
+                .query_row((storage.zoom, storage.x, storage.y), |row| row.get(0))
+                .optional()
+                .desc(format!("Can't read grid {}", coord))?;
+            if let Some(grid_zip) = grid_zip {
+                visitor(TileEntry::Grid(Grid {
+                    coord: *coord,
+                    utfgrid: self.read_grid(storage, grid_zip)?,
+                }))?;
+            }
+        }
+        Ok(())
+    }
+}
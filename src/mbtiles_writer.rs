@@ -0,0 +1,207 @@
+use crate::mbtile_error::{ErrorKind, MBTileError, ToMBTileResult};
+use crate::mbtiles::{
+    mbtiles_connect, mbtiles_setup, mbtiles_setup_deduplicated, optimize_connection,
+    optimize_database,
+};
+use crate::mbtiles_metadata::{insert_metadata, set_metadata_value};
+use crate::tile_coord::{TileCoord, TmsCoord};
+use crate::tile_hash::{create_tile_hashes, put_tile_hash, HashAlgorithm};
+use crate::tile_io::{Grid, Tile, TileSink};
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use md5::{Digest, Md5};
+use rusqlite::Connection;
+use serde_json::{Map, Value};
+use std::io::prelude::*;
+use std::path::Path;
+
+/// Writes tiles, UTFGrids and metadata into a new MBTiles file.
+///
+/// Call `finish` once everything is written to analyze and vacuum the database.
+pub struct MBTilesWriter {
+    connection: Connection,
+    tile_hashes: Option<HashAlgorithm>,
+    /// Whether the file has the deduplicated schema of `create_deduplicated`.
+    deduplicated: bool,
+}
+
+impl MBTilesWriter {
+    pub fn create<P: AsRef<Path>>(output: P) -> Result<MBTilesWriter, MBTileError> {
+        MBTilesWriter::open(output.as_ref(), false)
+    }
+
+    /// Writes into the deduplicated schema of mbutil, which stores the same tile data once:
+    /// `tiles`, `grids` and `grid_data` are views over `map`, `images`, `grid_utfgrid`,
+    /// `grid_key` and `keymap`.
+    ///
+    /// Like in mbutil, the `data` of a grid key is shared by every grid with that key.
+    pub fn create_deduplicated<P: AsRef<Path>>(output: P) -> Result<MBTilesWriter, MBTileError> {
+        MBTilesWriter::open(output.as_ref(), true)
+    }
+
+    fn open(output: &Path, deduplicated: bool) -> Result<MBTilesWriter, MBTileError> {
+        if output.exists() {
+            return Err(MBTileError::new(
+                ErrorKind::OutputExists,
+                format!("{:?} already exists", output),
+            ));
+        }
+        let connection = mbtiles_connect(output)?;
+        optimize_connection(&connection)?;
+        if deduplicated {
+            mbtiles_setup_deduplicated(&connection)?;
+        } else {
+            mbtiles_setup(&connection)?;
+        }
+        Ok(MBTilesWriter {
+            connection,
+            tile_hashes: None,
+            deduplicated,
+        })
+    }
+
+    /// Points the `map` entry of `storage` to a tile or a grid id.
+    fn put_map_id(&self, storage: TmsCoord, column: &str, id: &str) -> Result<(), MBTileError> {
+        self.connection
+            .prepare_cached(&format!(
+                "insert into map (zoom_level, tile_column, tile_row, {column}) values (?1, ?2, ?3, ?4)
+                on conflict (zoom_level, tile_column, tile_row) do update set {column} = ?4;",
+            ))?
+            .execute((storage.zoom, storage.x, storage.y, id))
+            .desc(format!("Can't insert {} in the map", storage))?;
+        Ok(())
+    }
+
+    /// Also stores the hash of each tile in a `tile_hashes` table, checked by `verify`.
+    pub fn with_tile_hashes(
+        mut self,
+        algorithm: HashAlgorithm,
+    ) -> Result<MBTilesWriter, MBTileError> {
+        create_tile_hashes(&self.connection)?;
+        self.tile_hashes = Some(algorithm);
+        Ok(self)
+    }
+
+    pub fn put_tile(&mut self, coord: TileCoord, data: &[u8]) -> Result<(), MBTileError> {
+        let storage = TmsCoord::try_from(coord)?;
+        if self.deduplicated {
+            // the tile id of mbutil
+            let tile_id = format!("{:x}", Md5::digest(data));
+            self.connection
+                .prepare_cached(
+                    "insert or ignore into images (tile_data, tile_id) values (?1, ?2);",
+                )?
+                .execute((data, &tile_id))
+                .desc(format!("Can't insert the image of tile {}", coord))?;
+            self.put_map_id(storage, "tile_id", &tile_id)?;
+        } else {
+            self.connection
+                .execute(
+                    "insert into tiles (zoom_level,
+                    tile_column, tile_row, tile_data) values
+                    (?1, ?2, ?3, ?4);",
+                    (storage.zoom, storage.x, storage.y, &data),
+                )
+                .desc(format!("Can't insert tile {}", coord))?;
+        }
+        if let Some(algorithm) = self.tile_hashes {
+            put_tile_hash(&self.connection, algorithm, coord, data)?;
+        }
+        Ok(())
+    }
+
+    /// Stores a UTFGrid: the `data` member goes to `grid_data`,
+    /// the rest is zipped into `grids`, or into `grid_key`, `keymap` and `grid_utfgrid`
+    /// with the deduplicated schema.
+    pub fn put_grid(&mut self, coord: TileCoord, utfgrid: &Value) -> Result<(), MBTileError> {
+        let storage = TmsCoord::try_from(coord)?;
+        let (data_opt, utfgrid_obj) = if let Value::Object(utfgrid_obj) = utfgrid {
+            let mut utfgrid_obj = utfgrid_obj.clone();
+            (utfgrid_obj.remove("data"), utfgrid_obj)
+        } else {
+            return Err(MBTileError::new(
+                ErrorKind::CorruptGrid,
+                "grid json not an object",
+            ));
+        };
+        let filtered_json_grid = serde_json::to_string(&utfgrid_obj)?;
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(filtered_json_grid.as_bytes())?;
+        let zipped_json = encoder.finish()?;
+        let grid_id = format!("{:x}", Md5::digest(filtered_json_grid.as_bytes()));
+        if self.deduplicated {
+            self.connection
+                .prepare_cached(
+                    "insert or ignore into grid_utfgrid (grid_id, grid_utfgrid) values (?1, ?2);",
+                )?
+                .execute((&grid_id, &zipped_json))
+                .desc("Can't insert zipped grid in database")?;
+            self.put_map_id(storage, "grid_id", &grid_id)?;
+        } else {
+            self.connection
+                .execute(
+                    "insert into grids (zoom_level, tile_column, tile_row, grid) values (?1, ?2, ?3, ?4);",
+                    (storage.zoom, storage.x, storage.y, &zipped_json),
+                )
+                .desc("Can't insert zipped grid in database")?;
+        }
+        if let Some(Value::Array(keys_array)) = utfgrid_obj.get("keys") {
+            let filtered_keys = keys_array.iter().filter_map(|k| {
+                k.as_str()
+                    .and_then(|k| if k.is_empty() { None } else { Some(k) })
+            });
+            for key in filtered_keys {
+                if let Some(ref data) = data_opt {
+                    if let Value::Object(ref data_obj) = *data {
+                        let Some(key_json) = data_obj.get(key) else {
+                            warn!("Grid {} has no data for key {:?}", coord, key);
+                            continue;
+                        };
+                        if self.deduplicated {
+                            self.connection.execute("insert or ignore into grid_key (grid_id, key_name) values (?1, ?2);",
+                                               (&grid_id, &key))?;
+                            self.connection.execute("insert or ignore into keymap (key_name, key_json) values (?1, ?2);",
+                                               (&key, &key_json.to_string()))?;
+                        } else {
+                            self.connection.execute("insert into grid_data (zoom_level, tile_column, tile_row, key_name, key_json) values (?1, ?2, ?3, ?4, ?5);",
+                                               (storage.zoom, storage.x, storage.y, &key, &key_json.to_string()))?;
+                        }
+                    } else {
+                        warn!("Can't get some data_obj {:?}", data);
+                    }
+                } else {
+                    warn!("Can't get some data {:?}", data_opt);
+                }
+            }
+        } else {
+            warn!("Can't get some json array {:?}", utfgrid_obj.get("keys"));
+        }
+        Ok(())
+    }
+
+    pub fn set_metadata(&mut self, name: &str, value: &str) -> Result<(), MBTileError> {
+        set_metadata_value(&self.connection, name, value)
+    }
+
+    pub fn finish(mut self) -> Result<(), MBTileError> {
+        TileSink::finish(&mut self)
+    }
+}
+
+impl TileSink for MBTilesWriter {
+    fn write_metadata(&mut self, metadata: &Map<String, Value>) -> Result<(), MBTileError> {
+        insert_metadata(&self.connection, metadata)
+    }
+
+    fn write_tile(&mut self, tile: &Tile) -> Result<(), MBTileError> {
+        self.put_tile(tile.coord, &tile.data)
+    }
+
+    fn write_grid(&mut self, grid: &Grid) -> Result<(), MBTileError> {
+        self.put_grid(grid.coord, &grid.utfgrid)
+    }
+
+    fn finish(&mut self) -> Result<(), MBTileError> {
+        optimize_database(&self.connection)
+    }
+}
@@ -0,0 +1,260 @@
+use crate::mbtile_error::{ErrorKind, MBTileError, ToMBTileResult};
+use crate::mbtiles::Scheme;
+use crate::tile_coord::TileCoord;
+use crate::tile_io::{Grid, Tile, TileEntry, TileSink, TileSource};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Lines, Write};
+use std::path::Path;
+
+/// How tiles are laid out outside of MBTiles files.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ExchangeFormat {
+    /// A directory tree of tile files.
+    #[default]
+    Directory,
+    /// One JSON object per line: the metadata, then each tile and grid.
+    Ndjson,
+}
+
+fn check_scheme(scheme: Scheme) -> Result<(), MBTileError> {
+    match scheme {
+        Scheme::Xyz | Scheme::Tms => Ok(()),
+        _ => Err(MBTileError::new(
+            ErrorKind::UnsupportedScheme,
+            format!("ndjson doesn't support the {} scheme", scheme),
+        )),
+    }
+}
+
+/// Writes a line-delimited JSON stream of
+/// `{"type": "metadata", "metadata": {...}}`,
+/// `{"type": "tile", "z": 0, "x": 0, "y": 0, "data": "<base64>"}` and
+/// `{"type": "grid", "z": 0, "x": 0, "y": 0, "grid": {...}}` objects,
+/// with rows numbered following `scheme`.
+pub struct NdjsonSink<W: Write> {
+    writer: W,
+    scheme: Scheme,
+}
+
+impl<W: Write> NdjsonSink<W> {
+    pub fn new(writer: W, scheme: Scheme) -> Result<NdjsonSink<W>, MBTileError> {
+        check_scheme(scheme)?;
+        Ok(NdjsonSink { writer, scheme })
+    }
+
+    fn row(&self, coord: TileCoord) -> Result<u32, MBTileError> {
+        match self.scheme {
+            Scheme::Tms => coord.tms_y(),
+            _ => Ok(coord.y),
+        }
+    }
+
+    fn write_line(&mut self, line: &Value) -> Result<(), MBTileError> {
+        // the errors of the writer, like a closed stdout, are IO errors
+        serde_json::to_writer(&mut self.writer, line)
+            .map_err(io::Error::from)
+            .desc("Can't write the ndjson stream")?;
+        self.writer
+            .write_all(b"\n")
+            .desc("Can't write the ndjson stream")
+    }
+}
+
+impl<W: Write> TileSink for NdjsonSink<W> {
+    fn write_metadata(&mut self, metadata: &Map<String, Value>) -> Result<(), MBTileError> {
+        self.write_line(&json!({"type": "metadata", "metadata": metadata}))
+    }
+
+    fn write_tile(&mut self, tile: &Tile) -> Result<(), MBTileError> {
+        let coord = tile.coord;
+        self.write_line(&json!({
+            "type": "tile",
+            "z": coord.zoom,
+            "x": coord.x,
+            "y": self.row(coord)?,
+            "data": BASE64.encode(&tile.data),
+        }))
+    }
+
+    fn write_grid(&mut self, grid: &Grid) -> Result<(), MBTileError> {
+        let coord = grid.coord;
+        self.write_line(&json!({
+            "type": "grid",
+            "z": coord.zoom,
+            "x": coord.x,
+            "y": self.row(coord)?,
+            "grid": grid.utfgrid,
+        }))
+    }
+
+    fn finish(&mut self) -> Result<(), MBTileError> {
+        self.writer.flush().desc("Can't write the ndjson stream")
+    }
+}
+
+/// Reads the stream written by `NdjsonSink`.
+///
+/// The metadata line is optional, an invalid line aborts the reading.
+pub struct NdjsonSource<R: BufRead> {
+    lines: Lines<R>,
+    line_number: u64,
+    scheme: Scheme,
+    /// First line read looking for the metadata, when it wasn't.
+    pending: Option<Value>,
+}
+
+impl<R: BufRead> NdjsonSource<R> {
+    pub fn new(reader: R, scheme: Scheme) -> Result<NdjsonSource<R>, MBTileError> {
+        check_scheme(scheme)?;
+        Ok(NdjsonSource {
+            lines: reader.lines(),
+            line_number: 0,
+            scheme,
+            pending: None,
+        })
+    }
+
+    fn invalid(&self, message: &str) -> MBTileError {
+        MBTileError::new(
+            ErrorKind::InvalidInput,
+            format!("Line {}: {}", self.line_number, message),
+        )
+    }
+
+    /// The next non-blank line, parsed.
+    fn next_value(&mut self) -> Result<Option<Value>, MBTileError> {
+        if let Some(value) = self.pending.take() {
+            return Ok(Some(value));
+        }
+        for line in self.lines.by_ref() {
+            self.line_number += 1;
+            let line = line.desc("Can't read the ndjson stream")?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            return serde_json::from_str(&line)
+                .desc(format!("Line {}: can't parse the JSON", self.line_number))
+                .map(Some);
+        }
+        Ok(None)
+    }
+
+    fn coord(&self, value: &Value) -> Result<TileCoord, MBTileError> {
+        let member = |name: &str| {
+            value
+                .get(name)
+                .and_then(Value::as_u64)
+                .and_then(|n| u32::try_from(n).ok())
+                .ok_or_else(|| self.invalid(&format!("no {:?} coordinate", name)))
+        };
+        let coord = TileCoord::new(member("z")?, member("x")?, member("y")?);
+        if !coord.is_valid() {
+            return Err(self.invalid(&format!("tile coordinate {} is out of range", coord)));
+        }
+        Ok(match self.scheme {
+            Scheme::Tms => TileCoord::from_tms(coord.zoom, coord.x, coord.y)?,
+            _ => coord,
+        })
+    }
+
+    fn parse_entry(&self, mut value: Value) -> Result<Option<TileEntry>, MBTileError> {
+        match value.get("type").and_then(Value::as_str) {
+            Some("tile") => {
+                let data = value
+                    .get("data")
+                    .and_then(Value::as_str)
+                    .ok_or_else(|| self.invalid("no tile data"))?;
+                let data = BASE64
+                    .decode(data)
+                    .map_err(|err| self.invalid(&format!("invalid base64 data: {}", err)))?;
+                Ok(Some(TileEntry::Tile(Tile {
+                    coord: self.coord(&value)?,
+                    data,
+                })))
+            }
+            Some("grid") => {
+                let coord = self.coord(&value)?;
+                match value.get_mut("grid").map(Value::take) {
+                    Some(utfgrid @ Value::Object(_)) => {
+                        Ok(Some(TileEntry::Grid(Grid { coord, utfgrid })))
+                    }
+                    _ => Err(self
+                        .invalid("the grid is not an object")
+                        .with_kind(ErrorKind::CorruptGrid)),
+                }
+            }
+            Some("metadata") => {
+                warn!("Line {}: ignoring metadata after tiles", self.line_number);
+                Ok(None)
+            }
+            _ => Err(self.invalid("not a tile, grid or metadata object")),
+        }
+    }
+}
+
+impl<R: BufRead> TileSource for NdjsonSource<R> {
+    fn read_metadata(&mut self) -> Result<Map<String, Value>, MBTileError> {
+        match self.next_value()? {
+            Some(value) if value.get("type").and_then(Value::as_str) == Some("metadata") => {
+                match value.get("metadata") {
+                    Some(Value::Object(metadata)) => Ok(metadata.clone()),
+                    _ => Err(self.invalid("the metadata is not an object")),
+                }
+            }
+            value => {
+                self.pending = value;
+                Ok(Map::new())
+            }
+        }
+    }
+
+    fn visit(
+        &mut self,
+        visitor: &mut dyn FnMut(TileEntry) -> Result<(), MBTileError>,
+    ) -> Result<(), MBTileError> {
+        while let Some(value) = self.next_value()? {
+            if let Some(entry) = self.parse_entry(value)? {
+                visitor(entry)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads `input`, or stdin when it is `-`.
+pub(crate) fn open_input(input: &Path) -> Result<Box<dyn BufRead>, MBTileError> {
+    if input == Path::new("-") {
+        return Ok(Box::new(io::stdin().lock()));
+    }
+    if !input.is_file() {
+        return Err(MBTileError::new(
+            ErrorKind::InputNotFound,
+            format!("{:?} is not a file", input),
+        ));
+    }
+    let file = File::open(input).desc(format!("Can't open {:?}", input))?;
+    Ok(Box::new(BufReader::new(file)))
+}
+
+/// Writes to `output`, or stdout when it is `-` or missing.
+pub(crate) fn create_output(
+    output: Option<&Path>,
+    overwrite: bool,
+) -> Result<Box<dyn Write>, MBTileError> {
+    let output = match output {
+        Some(output) if output != Path::new("-") => output,
+        _ => return Ok(Box::new(BufWriter::new(io::stdout().lock()))),
+    };
+    if output.exists() && !overwrite {
+        return Err(MBTileError::new(
+            ErrorKind::OutputExists,
+            format!("{:?} already exists", output),
+        ));
+    }
+    let file = File::create(output).desc(format!("Can't create {:?}", output))?;
+    Ok(Box::new(BufWriter::new(file)))
+}
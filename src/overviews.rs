@@ -0,0 +1,171 @@
+use crate::mbtile_error::{ErrorKind, MBTileError, ToMBTileResult};
+use crate::mbtiles::{mbtiles_open, ImageFormat};
+use crate::mbtiles_metadata::{read_metadata, set_metadata_value};
+use crate::raster::{
+    decode_raster, encode_raster, metadata_format, sniff_format, DEFAULT_JPEG_QUALITY,
+};
+use crate::tile_coord::TileCoord;
+use crate::tile_store::TileStore;
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, RgbaImage};
+use rusqlite::{Connection, OptionalExtension};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Tiles created by `build_overviews`, per zoom level.
+#[derive(Debug, Default)]
+pub struct OverviewsReport {
+    pub created: BTreeMap<u32, u64>,
+}
+
+impl OverviewsReport {
+    pub fn tiles(&self) -> u64 {
+        self.created.values().sum()
+    }
+}
+
+fn read_tile(connection: &Connection, coord: TileCoord) -> Result<Option<Vec<u8>>, MBTileError> {
+    connection
+        .prepare_cached(
+            "select tile_data from tiles
+            where zoom_level = ?1 and tile_column = ?2 and tile_row = ?3;",
+        )?
+        .query_row((coord.zoom, coord.x, coord.tms_y()?), |row| row.get(0))
+        .optional()
+        .desc(format!("Can't read tile {}", coord))
+}
+
+/// Columns and TMS rows of the tiles at `zoom` which have a tile at `zoom + 1`.
+fn parents(connection: &Connection, zoom: u32) -> Result<Vec<TileCoord>, MBTileError> {
+    let mut statement = connection.prepare(
+        "select distinct tile_column / 2, tile_row / 2 from tiles where zoom_level = ?1;",
+    )?;
+    let parents = statement
+        .query_map([zoom + 1], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<Vec<_>, _>>()
+        .desc(format!("Can't list the tiles of zoom {}", zoom + 1))?;
+    parents
+        .into_iter()
+        .map(|(x, y)| TileCoord::from_tms(zoom, x, y))
+        .collect()
+}
+
+/// Format of the tiles: the `format` metadata, or the format of a tile of `zoom`.
+fn tiles_format(connection: &Connection, zoom: u32) -> Result<ImageFormat, MBTileError> {
+    let format = match metadata_format(&read_metadata(connection)?) {
+        Some(format) => Some(format),
+        None => connection
+            .query_row(
+                "select tile_data from tiles where zoom_level = ?1 limit 1;",
+                [zoom],
+                |row| row.get::<usize, Vec<u8>>(0),
+            )
+            .optional()
+            .desc("Can't read a tile")?
+            .and_then(|data| sniff_format(&data)),
+    };
+    match format {
+        Some(ImageFormat::Pbf) => Err(MBTileError::new(
+            ErrorKind::InvalidInput,
+            "Can't build overviews of vector tiles",
+        )),
+        Some(format) => Ok(format),
+        None => Err(MBTileError::new(
+            ErrorKind::InvalidInput,
+            "Can't find the format of the tiles",
+        )),
+    }
+}
+
+/// Downsamples the four children of `parent` into one tile, missing children left transparent.
+fn composite(connection: &Connection, parent: TileCoord) -> Result<DynamicImage, MBTileError> {
+    let mut children = Vec::with_capacity(4);
+    let mut size = 0;
+    for child in parent.children().iter() {
+        let image = match read_tile(connection, *child)? {
+            Some(data) => {
+                let image = decode_raster(&data)
+                    .map_err(|err| err.with_kind(ErrorKind::CorruptTile))?
+                    .to_rgba8();
+                size = size.max(image.width());
+                Some(image)
+            }
+            None => None,
+        };
+        children.push(image);
+    }
+    let mut canvas = RgbaImage::new(size * 2, size * 2);
+    for (index, child) in children.iter().enumerate() {
+        if let Some(child) = child {
+            // children are top-left, top-right, bottom-left, bottom-right
+            let (dx, dy) = (index as u32 % 2, index as u32 / 2);
+            let child = if child.width() == size && child.height() == size {
+                child.clone()
+            } else {
+                imageops::resize(child, size, size, FilterType::Triangle)
+            };
+            imageops::replace(&mut canvas, &child, (dx * size) as i64, (dy * size) as i64);
+        }
+    }
+    Ok(DynamicImage::ImageRgba8(imageops::resize(
+        &canvas,
+        size,
+        size,
+        FilterType::Triangle,
+    )))
+}
+
+/// Creates the missing tiles from the highest zoom level of `input` down to `min_zoom`,
+/// each one downsampled from its four children and encoded like the other tiles.
+///
+/// Existing tiles are kept, and the `minzoom` metadata is lowered to the lowest zoom built.
+pub fn build_overviews<P: AsRef<Path>>(
+    input: P,
+    min_zoom: u32,
+) -> Result<OverviewsReport, MBTileError> {
+    let mut connection = mbtiles_open(input.as_ref())?;
+    let mut report = OverviewsReport::default();
+    let max_zoom: Option<u32> = connection
+        .query_row("select max(zoom_level) from tiles;", [], |row| row.get(0))
+        .desc("Can't read the zoom levels")?;
+    let max_zoom = max_zoom.ok_or_else(|| {
+        MBTileError::new(
+            ErrorKind::InvalidInput,
+            format!("{:?} has no tiles", input.as_ref()),
+        )
+    })?;
+    if min_zoom >= max_zoom {
+        return Ok(report);
+    }
+    let format = tiles_format(&connection, max_zoom)?;
+    for zoom in (min_zoom..max_zoom).rev() {
+        info!("Building the overviews of zoom {}", zoom);
+        let transaction = connection.transaction()?;
+        let store = TileStore::new(&transaction)?;
+        let mut created = 0;
+        for parent in parents(&transaction, zoom)? {
+            if read_tile(&transaction, parent)?.is_some() {
+                continue;
+            }
+            let data = encode_raster(
+                &composite(&transaction, parent)?,
+                format,
+                DEFAULT_JPEG_QUALITY,
+            )?;
+            store.put_tile(parent, &data)?;
+            created += 1;
+        }
+        transaction.commit()?;
+        if created > 0 {
+            report.created.insert(zoom, created);
+        }
+    }
+    if let Some(&lowest_zoom) = report.created.keys().next() {
+        let metadata = read_metadata(&connection)?;
+        let current_min_zoom = metadata.get("minzoom").and_then(|zoom| zoom.as_u64());
+        if current_min_zoom.is_none_or(|current| current > lowest_zoom as u64) {
+            set_metadata_value(&connection, "minzoom", &lowest_zoom.to_string())?;
+        }
+    }
+    Ok(report)
+}
@@ -0,0 +1,218 @@
+use crate::mbtile_error::{ErrorKind, MBTileError};
+use crate::mbtiles::{mbtiles_open, optimize_database};
+use crate::raster::{decode_raster, sniff_format};
+use crate::tile_coord::TileCoord;
+use crate::tile_io::{Grid, Tile, TileSink};
+use crate::tile_store::TileStore;
+use crate::vector_tile::decode_vector_tile;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+/// Which tiles to prune.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PruneMode {
+    /// Removes the fully transparent raster tiles and the vector tiles without features.
+    Empty,
+    /// Also removes the raster tiles of a single color.
+    Solid,
+    /// Removes the empty tiles, and stores the same data for the tiles of the same color.
+    ///
+    /// The data is only shared, and the storage saved, by the deduplicated schema:
+    /// import writes it, pruning in place fails on the files with a plain `tiles` table.
+    Dedupe,
+}
+
+/// Tiles pruned, per zoom level.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PruneReport {
+    pub removed: BTreeMap<u32, u64>,
+    pub deduplicated: BTreeMap<u32, u64>,
+}
+
+impl PruneReport {
+    pub fn removed_tiles(&self) -> u64 {
+        self.removed.values().sum()
+    }
+
+    pub fn deduplicated_tiles(&self) -> u64 {
+        self.deduplicated.values().sum()
+    }
+}
+
+enum TileContent {
+    Empty,
+    /// RGBA color of every pixel.
+    Solid([u8; 4]),
+    Other,
+}
+
+fn tile_content(data: &[u8]) -> Result<TileContent, MBTileError> {
+    if sniff_format(data).is_none() {
+        let layers = decode_vector_tile(data)?;
+        return Ok(if layers.iter().all(|layer| layer.features.is_empty()) {
+            TileContent::Empty
+        } else {
+            TileContent::Other
+        });
+    }
+    let image = decode_raster(data)?.to_rgba8();
+    let mut pixels = image.pixels();
+    let first = match pixels.next() {
+        Some(pixel) => pixel.0,
+        None => return Ok(TileContent::Empty),
+    };
+    if image.pixels().all(|pixel| pixel.0[3] == 0) {
+        Ok(TileContent::Empty)
+    } else if pixels.all(|pixel| pixel.0 == first) {
+        Ok(TileContent::Solid(first))
+    } else {
+        Ok(TileContent::Other)
+    }
+}
+
+enum PruneAction {
+    Keep,
+    Remove,
+    Replace(Vec<u8>),
+}
+
+/// Decides what happens to each tile, and counts it.
+pub(crate) struct Pruner {
+    mode: PruneMode,
+    /// The data kept for each solid color, when deduplicating.
+    solid_tiles: HashMap<[u8; 4], Vec<u8>>,
+    report: PruneReport,
+}
+
+impl Pruner {
+    pub(crate) fn new(mode: PruneMode) -> Pruner {
+        Pruner {
+            mode,
+            solid_tiles: HashMap::new(),
+            report: PruneReport::default(),
+        }
+    }
+
+    fn prune(&mut self, coord: TileCoord, data: &[u8]) -> PruneAction {
+        let content = match tile_content(data) {
+            Ok(content) => content,
+            Err(err) => {
+                warn!("Keeping tile {} which can't be read: {}", coord, err);
+                return PruneAction::Keep;
+            }
+        };
+        let action = match (content, self.mode) {
+            (TileContent::Empty, _) | (TileContent::Solid(_), PruneMode::Solid) => {
+                PruneAction::Remove
+            }
+            (TileContent::Solid(color), PruneMode::Dedupe) => {
+                let solid_tile = self
+                    .solid_tiles
+                    .entry(color)
+                    .or_insert_with(|| data.to_vec());
+                if solid_tile.as_slice() == data {
+                    PruneAction::Keep
+                } else {
+                    PruneAction::Replace(solid_tile.clone())
+                }
+            }
+            _ => PruneAction::Keep,
+        };
+        let counts = match action {
+            PruneAction::Keep => return action,
+            PruneAction::Remove => &mut self.report.removed,
+            PruneAction::Replace(_) => &mut self.report.deduplicated,
+        };
+        *counts.entry(coord.zoom).or_insert(0) += 1;
+        action
+    }
+
+    pub(crate) fn into_report(self) -> PruneReport {
+        self.report
+    }
+}
+
+/// Prunes the tiles before passing them to `sink`.
+pub(crate) struct PruneSink<'a> {
+    sink: &'a mut dyn TileSink,
+    pruner: &'a mut Pruner,
+}
+
+impl<'a> PruneSink<'a> {
+    pub(crate) fn new(sink: &'a mut dyn TileSink, pruner: &'a mut Pruner) -> PruneSink<'a> {
+        PruneSink { sink, pruner }
+    }
+}
+
+impl<'a> TileSink for PruneSink<'a> {
+    fn write_metadata(&mut self, metadata: &Map<String, Value>) -> Result<(), MBTileError> {
+        self.sink.write_metadata(metadata)
+    }
+
+    fn write_tile(&mut self, tile: &Tile) -> Result<(), MBTileError> {
+        match self.pruner.prune(tile.coord, &tile.data) {
+            PruneAction::Keep => self.sink.write_tile(tile),
+            PruneAction::Remove => Ok(()),
+            PruneAction::Replace(data) => self.sink.write_tile(&Tile {
+                coord: tile.coord,
+                data,
+            }),
+        }
+    }
+
+    fn write_grid(&mut self, grid: &Grid) -> Result<(), MBTileError> {
+        self.sink.write_grid(grid)
+    }
+
+    fn finish(&mut self) -> Result<(), MBTileError> {
+        self.sink.finish()
+    }
+}
+
+/// Prunes the tiles of an MBTiles file in place, then vacuums it.
+pub fn prune<P: AsRef<Path>>(input: P, mode: PruneMode) -> Result<PruneReport, MBTileError> {
+    let mut connection = mbtiles_open(input.as_ref())?;
+    if mode == PruneMode::Dedupe && !TileStore::new(&connection)?.is_deduplicated() {
+        return Err(MBTileError::new(
+            ErrorKind::SchemaMissing,
+            format!(
+                "{:?} has no map and images tables to share the data of the deduplicated tiles",
+                input.as_ref()
+            ),
+        ));
+    }
+    let mut pruner = Pruner::new(mode);
+    let mut actions = Vec::new();
+    {
+        let mut statement = connection
+            .prepare("select zoom_level, tile_column, tile_row, tile_data from tiles;")?;
+        let mut rows = statement.query(())?;
+        while let Some(row) = rows.next()? {
+            let coord = TileCoord::from_tms(row.get(0)?, row.get(1)?, row.get(2)?)?;
+            let data: Vec<u8> = row.get(3)?;
+            match pruner.prune(coord, &data) {
+                PruneAction::Keep => {}
+                action => actions.push((coord, action)),
+            }
+        }
+    }
+    let transaction = connection.transaction()?;
+    {
+        let store = TileStore::new(&transaction)?;
+        for (coord, action) in actions {
+            match action {
+                PruneAction::Remove => {
+                    store.delete(coord)?;
+                }
+                PruneAction::Replace(data) => store.put_tile(coord, &data)?,
+                PruneAction::Keep => {}
+            }
+        }
+        store.remove_orphans()?;
+    }
+    transaction.commit()?;
+    optimize_database(&connection)?;
+    Ok(pruner.into_report())
+}
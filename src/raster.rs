@@ -0,0 +1,89 @@
+use crate::mbtile_error::{ErrorKind, MBTileError, ToMBTileResult};
+use crate::mbtiles::ImageFormat;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::{CompressionType, FilterType as PngFilterType, PngEncoder};
+use image::codecs::webp::WebPEncoder;
+use image::{DynamicImage, ExtendedColorType, ImageEncoder};
+use serde_json::{Map, Value};
+
+/// JPEG quality used when none is asked for.
+pub(crate) const DEFAULT_JPEG_QUALITY: u8 = 85;
+
+/// Format of a tile, guessed from its first bytes.
+pub(crate) fn sniff_format(data: &[u8]) -> Option<ImageFormat> {
+    match image::guess_format(data) {
+        Ok(image::ImageFormat::Png) => Some(ImageFormat::Png),
+        Ok(image::ImageFormat::Jpeg) => Some(ImageFormat::Jpg),
+        Ok(image::ImageFormat::WebP) => Some(ImageFormat::Webp),
+        _ => None,
+    }
+}
+
+/// Format of the tiles as given by the `format` metadata.
+pub(crate) fn metadata_format(metadata: &Map<String, Value>) -> Option<ImageFormat> {
+    match metadata.get("format").and_then(|format| format.as_str()) {
+        Some("png") => Some(ImageFormat::Png),
+        Some("jpg") | Some("jpeg") => Some(ImageFormat::Jpg),
+        Some("webp") => Some(ImageFormat::Webp),
+        Some("pbf") => Some(ImageFormat::Pbf),
+        _ => None,
+    }
+}
+
+pub(crate) fn decode_raster(data: &[u8]) -> Result<DynamicImage, MBTileError> {
+    image::load_from_memory(data).desc("Can't decode the tile image")
+}
+
+/// Encodes `image` as `format`, JPEG tiles losing their transparency.
+///
+/// PNG are compressed at the best level, without alpha channel when opaque.
+/// WebP are lossless, `jpeg_quality` only applies to JPEG.
+pub(crate) fn encode_raster(
+    image: &DynamicImage,
+    format: ImageFormat,
+    jpeg_quality: u8,
+) -> Result<Vec<u8>, MBTileError> {
+    let mut data = Vec::new();
+    match format {
+        ImageFormat::Png => {
+            let encoder = PngEncoder::new_with_quality(
+                &mut data,
+                CompressionType::Best,
+                PngFilterType::Adaptive,
+            );
+            let rgba = image.to_rgba8();
+            if rgba.pixels().all(|pixel| pixel.0[3] == 255) {
+                let rgb = image.to_rgb8();
+                encoder.write_image(&rgb, rgb.width(), rgb.height(), ExtendedColorType::Rgb8)
+            } else {
+                encoder.write_image(&rgba, rgba.width(), rgba.height(), ExtendedColorType::Rgba8)
+            }
+        }
+        ImageFormat::Jpg => {
+            let rgb = image.to_rgb8();
+            JpegEncoder::new_with_quality(&mut data, jpeg_quality).write_image(
+                &rgb,
+                rgb.width(),
+                rgb.height(),
+                ExtendedColorType::Rgb8,
+            )
+        }
+        ImageFormat::Webp => {
+            let rgba = image.to_rgba8();
+            WebPEncoder::new_lossless(&mut data).write_image(
+                &rgba,
+                rgba.width(),
+                rgba.height(),
+                ExtendedColorType::Rgba8,
+            )
+        }
+        ImageFormat::Pbf => {
+            return Err(MBTileError::new(
+                ErrorKind::InvalidInput,
+                "Vector tiles are not images",
+            ))
+        }
+    }
+    .desc("Can't encode the tile image")?;
+    Ok(data)
+}
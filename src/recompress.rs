@@ -0,0 +1,130 @@
+use crate::mbtile_error::{ErrorKind, MBTileError};
+use crate::mbtiles::ImageFormat;
+use crate::mbtiles_reader::MBTilesReader;
+use crate::mbtiles_writer::MBTilesWriter;
+use crate::raster::{decode_raster, encode_raster, metadata_format, sniff_format};
+use crate::tile_io::{copy_with_progress, Grid, Progress, Tile, TileSink};
+use serde_json::{Map, Value};
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RecompressOptions {
+    pub image_format: ImageFormat,
+    /// JPEG quality, from 1 to 100.
+    pub quality: u8,
+}
+
+/// What a recompression did to the tiles.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RecompressReport {
+    pub tiles: u64,
+    /// Tiles written re-encoded.
+    pub recompressed: u64,
+    /// Tiles already in the format whose re-encoding was larger, written as they were.
+    pub kept: u64,
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+}
+
+/// Re-encodes the tiles before passing them to `sink`.
+struct RecompressSink<'a> {
+    sink: &'a mut dyn TileSink,
+    options: RecompressOptions,
+    report: RecompressReport,
+}
+
+impl<'a> RecompressSink<'a> {
+    fn recompress(&mut self, tile: &Tile) -> Result<Vec<u8>, MBTileError> {
+        let image =
+            decode_raster(&tile.data).map_err(|err| err.with_kind(ErrorKind::CorruptTile))?;
+        let data = encode_raster(&image, self.options.image_format, self.options.quality)?;
+        // a tile in another format is always converted
+        if data.len() >= tile.data.len()
+            && sniff_format(&tile.data) == Some(self.options.image_format)
+        {
+            self.report.kept += 1;
+            Ok(tile.data.clone())
+        } else {
+            self.report.recompressed += 1;
+            Ok(data)
+        }
+    }
+}
+
+impl<'a> TileSink for RecompressSink<'a> {
+    fn write_metadata(&mut self, metadata: &Map<String, Value>) -> Result<(), MBTileError> {
+        if metadata_format(metadata) == Some(ImageFormat::Pbf) {
+            return Err(MBTileError::new(
+                ErrorKind::InvalidInput,
+                "Can't recompress vector tiles",
+            ));
+        }
+        let mut metadata = metadata.clone();
+        metadata.insert(
+            "format".to_owned(),
+            Value::from(self.options.image_format.to_string()),
+        );
+        self.sink.write_metadata(&metadata)
+    }
+
+    fn write_tile(&mut self, tile: &Tile) -> Result<(), MBTileError> {
+        let data = self.recompress(tile).map_err(|mut err| {
+            err.message = Some(format!("Tile {}: {}", tile.coord, err));
+            err
+        })?;
+        self.report.tiles += 1;
+        self.report.bytes_before += tile.data.len() as u64;
+        self.report.bytes_after += data.len() as u64;
+        self.sink.write_tile(&Tile {
+            coord: tile.coord,
+            data,
+        })
+    }
+
+    fn write_grid(&mut self, grid: &Grid) -> Result<(), MBTileError> {
+        self.sink.write_grid(grid)
+    }
+
+    fn finish(&mut self) -> Result<(), MBTileError> {
+        self.sink.finish()
+    }
+}
+
+/// Copies `input` into a new `output` MBTiles file with every tile re-encoded
+/// following `options`, and the `format` metadata updated.
+pub fn recompress<P: AsRef<Path>>(
+    input: P,
+    output: P,
+    options: &RecompressOptions,
+) -> Result<RecompressReport, MBTileError> {
+    recompress_with_progress(input, output, options, &mut |_| {})
+}
+
+pub fn recompress_with_progress<P: AsRef<Path>>(
+    input: P,
+    output: P,
+    options: &RecompressOptions,
+    progress: &mut dyn FnMut(&Progress),
+) -> Result<RecompressReport, MBTileError> {
+    if options.image_format == ImageFormat::Pbf {
+        return Err(MBTileError::new(
+            ErrorKind::InvalidInput,
+            "Can't recompress tiles to pbf",
+        ));
+    }
+    if !(1..=100).contains(&options.quality) {
+        return Err(MBTileError::new(
+            ErrorKind::InvalidInput,
+            format!("Quality {} is not between 1 and 100", options.quality),
+        ));
+    }
+    let mut reader = MBTilesReader::open(input.as_ref())?;
+    let mut writer = MBTilesWriter::create(output.as_ref())?;
+    let mut sink = RecompressSink {
+        sink: &mut writer,
+        options: *options,
+        report: RecompressReport::default(),
+    };
+    copy_with_progress(&mut reader, &mut sink, progress)?;
+    Ok(sink.report)
+}
@@ -0,0 +1,394 @@
+use crate::mbtile_error::{ErrorKind, MBTileError, ToMBTileResult};
+use std::f64::consts::PI;
+use std::fmt;
+use std::str::FromStr;
+
+/// Latitude limit of the Web Mercator projection.
+pub const MAX_LATITUDE: f64 = 85.051_128_779_806_59;
+
+/// Flips a row between the XYZ (rows from the north) and TMS (rows from the south) numberings.
+///
+/// `None` when the row is out of the range of the zoom level.
+pub fn flip_y(zoom: u32, y: u32) -> Option<u32> {
+    1u32.checked_shl(zoom)?.checked_sub(1)?.checked_sub(y)
+}
+
+/// Longitude and latitude of the north-west corner of an XYZ tile.
+pub fn tile_to_lng_lat(zoom: u32, x: u32, y: u32) -> (f64, f64) {
+    let n = 2f64.powi(zoom as i32);
+    let lng = x as f64 / n * 360.0 - 180.0;
+    let lat = (PI * (1.0 - 2.0 * y as f64 / n)).sinh().atan().to_degrees();
+    (lng, lat)
+}
+
+/// XYZ column and row of the tile containing a point, clamped to the world.
+pub fn lng_lat_to_tile(lng: f64, lat: f64, zoom: u32) -> (u32, u32) {
+    let n = 2f64.powi(zoom as i32);
+    let lat_rad = lat.clamp(-MAX_LATITUDE, MAX_LATITUDE).to_radians();
+    let x = (lng + 180.0) / 360.0 * n;
+    let y = (1.0 - (lat_rad.tan() + 1.0 / lat_rad.cos()).ln() / PI) / 2.0 * n;
+    let max = n - 1.0;
+    (
+        x.floor().clamp(0.0, max) as u32,
+        y.floor().clamp(0.0, max) as u32,
+    )
+}
+
+/// A tile in the XYZ numbering, rows counted from the north.
+///
+/// The `tiles` table stores TMS rows: use `from_tms` and `tms_y` at that boundary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TileCoord {
+    pub zoom: u32,
+    pub x: u32,
+    pub y: u32,
+}
+
+impl TileCoord {
+    pub fn new(zoom: u32, x: u32, y: u32) -> TileCoord {
+        TileCoord { zoom, x, y }
+    }
+
+    /// Fails on a row or a column out of the range of the zoom level, as in a corrupt file.
+    pub fn from_tms(zoom: u32, x: u32, tms_y: u32) -> Result<TileCoord, MBTileError> {
+        let coord = TileCoord::new(zoom, x, tms_y);
+        if !coord.is_valid() {
+            return Err(MBTileError::new(
+                ErrorKind::InvalidInput,
+                format!("Tile {} (TMS) is out of range", coord),
+            ));
+        }
+        Ok(TileCoord::new(zoom, x, coord.tms_y()?))
+    }
+
+    pub fn from_lng_lat(lng: f64, lat: f64, zoom: u32) -> TileCoord {
+        let (x, y) = lng_lat_to_tile(lng, lat, zoom);
+        TileCoord::new(zoom, x, y)
+    }
+
+    /// Fails on a row or a column out of the range of the zoom level.
+    pub fn tms_y(&self) -> Result<u32, MBTileError> {
+        match flip_y(self.zoom, self.y) {
+            Some(tms_y) if self.is_valid() => Ok(tms_y),
+            _ => Err(MBTileError::new(
+                ErrorKind::InvalidInput,
+                format!("Tile coordinate {} is out of range", self),
+            )),
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.zoom < 32 && self.x < 2u32.pow(self.zoom) && self.y < 2u32.pow(self.zoom)
+    }
+
+    pub fn bounds(&self) -> LngLatBounds {
+        let (west, north) = tile_to_lng_lat(self.zoom, self.x, self.y);
+        let (east, south) = tile_to_lng_lat(self.zoom, self.x + 1, self.y + 1);
+        LngLatBounds::new(west, south, east, north)
+    }
+
+    pub fn parent(&self) -> Option<TileCoord> {
+        if self.zoom == 0 {
+            None
+        } else {
+            Some(TileCoord::new(self.zoom - 1, self.x / 2, self.y / 2))
+        }
+    }
+
+    /// The four tiles of the next zoom: top-left, top-right, bottom-left, bottom-right.
+    pub fn children(&self) -> [TileCoord; 4] {
+        let (zoom, x, y) = (self.zoom + 1, self.x * 2, self.y * 2);
+        [
+            TileCoord::new(zoom, x, y),
+            TileCoord::new(zoom, x + 1, y),
+            TileCoord::new(zoom, x, y + 1),
+            TileCoord::new(zoom, x + 1, y + 1),
+        ]
+    }
+}
+
+impl fmt::Display for TileCoord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}/{}", self.zoom, self.x, self.y)
+    }
+}
+
+/// A tile as stored in the MBTiles tables, rows counted from the south.
+///
+/// Keeps storage rows apart from the XYZ rows of `TileCoord` in queries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TmsCoord {
+    pub zoom: u32,
+    pub x: u32,
+    pub y: u32,
+}
+
+impl TmsCoord {
+    pub fn new(zoom: u32, x: u32, y: u32) -> TmsCoord {
+        TmsCoord { zoom, x, y }
+    }
+}
+
+/// Fails on the coordinates out of the range of their zoom level.
+impl TryFrom<TileCoord> for TmsCoord {
+    type Error = MBTileError;
+
+    fn try_from(coord: TileCoord) -> Result<TmsCoord, MBTileError> {
+        Ok(TmsCoord::new(coord.zoom, coord.x, coord.tms_y()?))
+    }
+}
+
+/// Fails on the rows of a corrupt file, out of the range of their zoom level.
+impl TryFrom<TmsCoord> for TileCoord {
+    type Error = MBTileError;
+
+    fn try_from(coord: TmsCoord) -> Result<TileCoord, MBTileError> {
+        TileCoord::from_tms(coord.zoom, coord.x, coord.y)
+    }
+}
+
+impl fmt::Display for TmsCoord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}/{} (TMS)", self.zoom, self.x, self.y)
+    }
+}
+
+/// Parses `z/x/y`.
+impl FromStr for TileCoord {
+    type Err = MBTileError;
+
+    fn from_str(s: &str) -> Result<TileCoord, MBTileError> {
+        let parts: Vec<&str> = s.trim().split('/').collect();
+        if parts.len() != 3 {
+            return Err(MBTileError::new(
+                ErrorKind::InvalidInput,
+                format!("Tile coordinate {:?} is not z/x/y", s),
+            ));
+        }
+        let coord = TileCoord::new(
+            parts[0]
+                .parse::<u32>()
+                .desc(format!("Can't parse zoom of {:?}", s))?,
+            parts[1]
+                .parse::<u32>()
+                .desc(format!("Can't parse x of {:?}", s))?,
+            parts[2]
+                .parse::<u32>()
+                .desc(format!("Can't parse y of {:?}", s))?,
+        );
+        if !coord.is_valid() {
+            return Err(MBTileError::new(
+                ErrorKind::InvalidInput,
+                format!("Tile coordinate {} is out of range", coord),
+            ));
+        }
+        Ok(coord)
+    }
+}
+
+/// An inclusive rectangle of XYZ tiles at one zoom level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TileRange {
+    pub zoom: u32,
+    pub min_x: u32,
+    pub min_y: u32,
+    pub max_x: u32,
+    pub max_y: u32,
+}
+
+impl TileRange {
+    pub fn new(zoom: u32, min_x: u32, min_y: u32, max_x: u32, max_y: u32) -> TileRange {
+        TileRange {
+            zoom,
+            min_x,
+            min_y,
+            max_x,
+            max_y,
+        }
+    }
+
+    /// Every tile of a zoom level.
+    pub fn world(zoom: u32) -> Result<TileRange, MBTileError> {
+        let max = flip_y(zoom, 0).ok_or_else(|| {
+            MBTileError::new(
+                ErrorKind::InvalidInput,
+                format!("Zoom level {} is out of range", zoom),
+            )
+        })?;
+        Ok(TileRange::new(zoom, 0, 0, max, max))
+    }
+
+    /// The tiles covering `bounds`.
+    pub fn from_bounds(bounds: &LngLatBounds, zoom: u32) -> TileRange {
+        // tiles only touching the east or south edges are not covered
+        let epsilon = 1e-9;
+        let east = (bounds.east - epsilon).max(bounds.west);
+        let south = (bounds.south + epsilon).min(bounds.north);
+        let (min_x, min_y) = lng_lat_to_tile(bounds.west, bounds.north, zoom);
+        let (max_x, max_y) = lng_lat_to_tile(east, south, zoom);
+        TileRange::new(zoom, min_x, min_y, max_x, max_y)
+    }
+
+    pub fn contains(&self, coord: &TileCoord) -> bool {
+        coord.zoom == self.zoom
+            && (self.min_x..=self.max_x).contains(&coord.x)
+            && (self.min_y..=self.max_y).contains(&coord.y)
+    }
+
+    pub fn len(&self) -> u64 {
+        if self.is_empty() {
+            0
+        } else {
+            (self.max_x - self.min_x + 1) as u64 * (self.max_y - self.min_y + 1) as u64
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min_x > self.max_x || self.min_y > self.max_y
+    }
+
+    pub fn bounds(&self) -> LngLatBounds {
+        let (west, north) = tile_to_lng_lat(self.zoom, self.min_x, self.min_y);
+        let (east, south) = tile_to_lng_lat(self.zoom, self.max_x + 1, self.max_y + 1);
+        LngLatBounds::new(west, south, east, north)
+    }
+
+    /// Iterates row by row, from the north-west tile.
+    pub fn iter(&self) -> TileRangeIter {
+        TileRangeIter {
+            range: *self,
+            next: if self.is_empty() {
+                None
+            } else {
+                Some((self.min_x, self.min_y))
+            },
+        }
+    }
+}
+
+impl IntoIterator for &TileRange {
+    type Item = TileCoord;
+    type IntoIter = TileRangeIter;
+
+    fn into_iter(self) -> TileRangeIter {
+        self.iter()
+    }
+}
+
+pub struct TileRangeIter {
+    range: TileRange,
+    next: Option<(u32, u32)>,
+}
+
+impl Iterator for TileRangeIter {
+    type Item = TileCoord;
+
+    fn next(&mut self) -> Option<TileCoord> {
+        let (x, y) = self.next?;
+        self.next = if x < self.range.max_x {
+            Some((x + 1, y))
+        } else if y < self.range.max_y {
+            Some((self.range.min_x, y + 1))
+        } else {
+            None
+        };
+        Some(TileCoord::new(self.range.zoom, x, y))
+    }
+}
+
+/// A geographic rectangle in degrees, as in the `bounds` metadata.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LngLatBounds {
+    pub west: f64,
+    pub south: f64,
+    pub east: f64,
+    pub north: f64,
+}
+
+impl LngLatBounds {
+    pub fn new(west: f64, south: f64, east: f64, north: f64) -> LngLatBounds {
+        LngLatBounds {
+            west,
+            south,
+            east,
+            north,
+        }
+    }
+
+    /// The whole Web Mercator world.
+    pub fn world() -> LngLatBounds {
+        LngLatBounds::new(-180.0, -MAX_LATITUDE, 180.0, MAX_LATITUDE)
+    }
+
+    pub fn contains(&self, lng: f64, lat: f64) -> bool {
+        (self.west..=self.east).contains(&lng) && (self.south..=self.north).contains(&lat)
+    }
+
+    pub fn intersects(&self, other: &LngLatBounds) -> bool {
+        self.west < other.east
+            && other.west < self.east
+            && self.south < other.north
+            && other.south < self.north
+    }
+
+    /// The bounds common to both, if they intersect.
+    pub fn intersection(&self, other: &LngLatBounds) -> Option<LngLatBounds> {
+        if self.intersects(other) {
+            Some(LngLatBounds::new(
+                self.west.max(other.west),
+                self.south.max(other.south),
+                self.east.min(other.east),
+                self.north.min(other.north),
+            ))
+        } else {
+            None
+        }
+    }
+
+    /// The smallest bounds containing both.
+    pub fn union(&self, other: &LngLatBounds) -> LngLatBounds {
+        LngLatBounds::new(
+            self.west.min(other.west),
+            self.south.min(other.south),
+            self.east.max(other.east),
+            self.north.max(other.north),
+        )
+    }
+}
+
+impl fmt::Display for LngLatBounds {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{},{},{},{}",
+            self.west, self.south, self.east, self.north
+        )
+    }
+}
+
+/// Parses `west,south,east,north`.
+impl FromStr for LngLatBounds {
+    type Err = MBTileError;
+
+    fn from_str(s: &str) -> Result<LngLatBounds, MBTileError> {
+        let values = s
+            .split(',')
+            .map(|v| v.trim().parse::<f64>())
+            .collect::<Result<Vec<f64>, _>>()
+            .map_err(|_| {
+                MBTileError::new(
+                    ErrorKind::InvalidInput,
+                    format!("Can't parse bounds {:?}", s),
+                )
+            })?;
+        if values.len() != 4 {
+            return Err(MBTileError::new(
+                ErrorKind::InvalidInput,
+                format!("Bounds {:?} are not west,south,east,north", s),
+            ));
+        }
+        Ok(LngLatBounds::new(
+            values[0], values[1], values[2], values[3],
+        ))
+    }
+}
@@ -0,0 +1,201 @@
+use crate::mbtile_error::{MBTileError, ToMBTileResult};
+use crate::mbtiles::Scheme;
+use crate::tile_coord::{LngLatBounds, TileCoord};
+use crate::tile_io::{Grid, Tile, TileEntry, TileSink, TileSource};
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+/// Selects tiles by zoom range, geographic bounds and coordinates.
+///
+/// A tile is selected when it matches every criterion set.
+#[derive(Debug, Clone, Default)]
+pub struct TileFilter {
+    pub min_zoom: Option<u32>,
+    pub max_zoom: Option<u32>,
+    /// Tiles intersecting these bounds.
+    pub bounds: Option<LngLatBounds>,
+    pub tiles: Option<HashSet<TileCoord>>,
+}
+
+impl TileFilter {
+    pub fn new() -> TileFilter {
+        TileFilter::default()
+    }
+
+    pub fn with_zoom_range(mut self, min_zoom: Option<u32>, max_zoom: Option<u32>) -> TileFilter {
+        self.min_zoom = min_zoom;
+        self.max_zoom = max_zoom;
+        self
+    }
+
+    pub fn with_bounds(mut self, bounds: LngLatBounds) -> TileFilter {
+        self.bounds = Some(bounds);
+        self
+    }
+
+    pub fn with_tiles(mut self, tiles: HashSet<TileCoord>) -> TileFilter {
+        self.tiles = Some(tiles);
+        self
+    }
+
+    /// Whether every tile is selected.
+    pub fn selects_all(&self) -> bool {
+        self.min_zoom.is_none_or(|min_zoom| min_zoom == 0)
+            && self.max_zoom.is_none()
+            && self.bounds.is_none()
+            && self.tiles.is_none()
+    }
+
+    pub fn contains(&self, coord: &TileCoord) -> bool {
+        self.min_zoom.is_none_or(|min_zoom| coord.zoom >= min_zoom)
+            && self.max_zoom.is_none_or(|max_zoom| coord.zoom <= max_zoom)
+            && self
+                .bounds
+                .is_none_or(|bounds| bounds.intersects(&coord.bounds()))
+            && self
+                .tiles
+                .as_ref()
+                .is_none_or(|tiles| tiles.contains(coord))
+    }
+}
+
+/// Reads a list of `z/x/y` coordinates, one per line, with rows numbered following `scheme`:
+/// from the south for `tms` and `wms`, from the north for `xyz` and `ags`.
+///
+/// Blank lines and lines starting with `#` are skipped.
+pub fn read_tile_list<P: AsRef<Path>>(
+    path: P,
+    scheme: Scheme,
+) -> Result<HashSet<TileCoord>, MBTileError> {
+    let list = fs::read_to_string(path.as_ref())
+        .desc(format!("Can't read the tile list {:?}", path.as_ref()))?;
+    list.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let coord: TileCoord = line.parse()?;
+            match scheme {
+                Scheme::Tms | Scheme::Wms => TileCoord::from_tms(coord.zoom, coord.x, coord.y),
+                Scheme::Xyz | Scheme::Ags => Ok(coord),
+            }
+        })
+        .collect()
+}
+
+/// The tiles and grids of `source` selected by `filter`.
+pub struct FilteredSource<'a> {
+    source: &'a mut dyn TileSource,
+    filter: &'a TileFilter,
+}
+
+impl<'a> FilteredSource<'a> {
+    pub fn new(source: &'a mut dyn TileSource, filter: &'a TileFilter) -> FilteredSource<'a> {
+        FilteredSource { source, filter }
+    }
+}
+
+impl<'a> TileSource for FilteredSource<'a> {
+    fn read_metadata(&mut self) -> Result<Map<String, Value>, MBTileError> {
+        self.source.read_metadata()
+    }
+
+    fn count(&mut self) -> Result<Option<u64>, MBTileError> {
+        if self.filter.selects_all() {
+            self.source.count()
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn visit(
+        &mut self,
+        visitor: &mut dyn FnMut(TileEntry) -> Result<(), MBTileError>,
+    ) -> Result<(), MBTileError> {
+        let filter = self.filter;
+        let mut visit_selected = |entry: TileEntry| {
+            if filter.contains(&entry.coord()) {
+                visitor(entry)
+            } else {
+                Ok(())
+            }
+        };
+        match filter.tiles {
+            Some(ref tiles) => self.source.visit_tiles(tiles, &mut visit_selected),
+            None => self.source.visit(&mut visit_selected),
+        }
+    }
+}
+
+/// Rewrites the `bounds`, `minzoom` and `maxzoom` metadata from the tiles written,
+/// once they all are.
+///
+/// The bounds are those of the tiles of the highest zoom, within `clip` if given.
+pub(crate) struct ExtentSink<'a> {
+    sink: &'a mut dyn TileSink,
+    clip: Option<LngLatBounds>,
+    metadata: Map<String, Value>,
+    zooms: Option<(u32, u32)>,
+    bounds: Option<LngLatBounds>,
+}
+
+impl<'a> ExtentSink<'a> {
+    pub(crate) fn new(sink: &'a mut dyn TileSink, clip: Option<LngLatBounds>) -> ExtentSink<'a> {
+        ExtentSink {
+            sink,
+            clip,
+            metadata: Map::new(),
+            zooms: None,
+            bounds: None,
+        }
+    }
+}
+
+impl<'a> TileSink for ExtentSink<'a> {
+    fn write_metadata(&mut self, metadata: &Map<String, Value>) -> Result<(), MBTileError> {
+        self.metadata = metadata.clone();
+        self.sink.write_metadata(metadata)
+    }
+
+    fn write_tile(&mut self, tile: &Tile) -> Result<(), MBTileError> {
+        let zoom = tile.coord.zoom;
+        let bounds = tile.coord.bounds();
+        match self.zooms {
+            Some((min_zoom, max_zoom)) if zoom <= max_zoom => {
+                if zoom == max_zoom {
+                    self.bounds = self.bounds.map(|b| b.union(&bounds));
+                }
+                self.zooms = Some((min_zoom.min(zoom), max_zoom));
+            }
+            Some((min_zoom, _)) => {
+                self.zooms = Some((min_zoom, zoom));
+                self.bounds = Some(bounds);
+            }
+            None => {
+                self.zooms = Some((zoom, zoom));
+                self.bounds = Some(bounds);
+            }
+        }
+        self.sink.write_tile(tile)
+    }
+
+    fn write_grid(&mut self, grid: &Grid) -> Result<(), MBTileError> {
+        self.sink.write_grid(grid)
+    }
+
+    fn finish(&mut self) -> Result<(), MBTileError> {
+        if let (Some((min_zoom, max_zoom)), Some(bounds)) = (self.zooms, self.bounds) {
+            let bounds = match self.clip {
+                Some(clip) => clip.intersection(&bounds).unwrap_or(bounds),
+                None => bounds,
+            };
+            let mut metadata = self.metadata.clone();
+            metadata.insert("bounds".to_owned(), Value::from(bounds.to_string()));
+            metadata.insert("minzoom".to_owned(), Value::from(min_zoom));
+            metadata.insert("maxzoom".to_owned(), Value::from(max_zoom));
+            self.sink.write_metadata(&metadata)?;
+        }
+        self.sink.finish()
+    }
+}
@@ -0,0 +1,159 @@
+use crate::mbtile_error::{ErrorKind, MBTileError, ToMBTileResult};
+use crate::mbtiles::{has_table, mbtiles_open};
+use crate::tile_coord::TileCoord;
+use md5::{Digest, Md5};
+use rusqlite::Connection;
+use serde::Deserialize;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Hash stored for each tile in the `tile_hashes` table.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Md5,
+    /// 64 bits XXH3, much faster than MD5.
+    Xxh3,
+}
+
+impl HashAlgorithm {
+    /// Hex digest of `data`.
+    pub fn hash(self, data: &[u8]) -> String {
+        match self {
+            HashAlgorithm::Md5 => format!("{:x}", Md5::digest(data)),
+            HashAlgorithm::Xxh3 => format!("{:016x}", xxhash_rust::xxh3::xxh3_64(data)),
+        }
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let res = match *self {
+            HashAlgorithm::Md5 => "md5",
+            HashAlgorithm::Xxh3 => "xxh3",
+        };
+        write!(f, "{}", res)
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = MBTileError;
+
+    fn from_str(s: &str) -> Result<HashAlgorithm, MBTileError> {
+        match s {
+            "md5" => Ok(HashAlgorithm::Md5),
+            "xxh3" => Ok(HashAlgorithm::Xxh3),
+            _ => Err(MBTileError::new(
+                ErrorKind::InvalidInput,
+                format!("Unknown hash algorithm {:?}", s),
+            )),
+        }
+    }
+}
+
+pub(crate) fn create_tile_hashes(connection: &Connection) -> Result<(), MBTileError> {
+    connection
+        .execute_batch(
+            "
+        CREATE TABLE tile_hashes (zoom_level INTEGER, tile_column INTEGER,
+            tile_row INTEGER, algorithm TEXT, hash TEXT);
+        CREATE UNIQUE INDEX tile_hashes_index ON tile_hashes
+            (zoom_level, tile_column, tile_row);
+    ",
+        )
+        .desc("Can't create the tile_hashes table")
+}
+
+/// Stores the hash of the tile `data` at `coord`, replacing the previous one.
+pub(crate) fn put_tile_hash(
+    connection: &Connection,
+    algorithm: HashAlgorithm,
+    coord: TileCoord,
+    data: &[u8],
+) -> Result<(), MBTileError> {
+    connection
+        .prepare_cached(
+            "insert or replace into tile_hashes
+            (zoom_level, tile_column, tile_row, algorithm, hash) values (?1, ?2, ?3, ?4, ?5);",
+        )?
+        .execute((
+            coord.zoom,
+            coord.x,
+            coord.tms_y()?,
+            algorithm.to_string(),
+            algorithm.hash(data),
+        ))
+        .desc(format!("Can't insert the hash of tile {}", coord))?;
+    Ok(())
+}
+
+/// Tiles which don't match their stored hash.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct VerifyReport {
+    /// Tiles checked against their hash.
+    pub tiles: u64,
+    /// Tiles whose data has another hash.
+    pub mismatched: Vec<TileCoord>,
+    /// Tiles without hash.
+    pub unhashed: Vec<TileCoord>,
+    /// Hashes without tile.
+    pub missing: Vec<TileCoord>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.mismatched.is_empty() && self.unhashed.is_empty() && self.missing.is_empty()
+    }
+}
+
+/// Recomputes the hash of every tile of an MBTiles file imported with tile hashes,
+/// and compares it to the stored one.
+pub fn verify<P: AsRef<Path>>(input: P) -> Result<VerifyReport, MBTileError> {
+    let connection = mbtiles_open(input.as_ref())?;
+    if !has_table(&connection, "tile_hashes")? {
+        return Err(MBTileError::new(
+            ErrorKind::SchemaMissing,
+            format!("{:?} has no tile hashes", input.as_ref()),
+        ));
+    }
+    let mut report = VerifyReport::default();
+    let mut statement = connection.prepare(
+        "select tiles.zoom_level, tiles.tile_column, tiles.tile_row, tile_data, algorithm, hash
+        from tiles left join tile_hashes on tile_hashes.zoom_level = tiles.zoom_level
+            and tile_hashes.tile_column = tiles.tile_column
+            and tile_hashes.tile_row = tiles.tile_row
+        order by tiles.zoom_level, tiles.tile_column, tiles.tile_row;",
+    )?;
+    let mut rows = statement.query(())?;
+    while let Some(row) = rows.next().desc("Can't read the tiles")? {
+        let coord = TileCoord::from_tms(row.get(0)?, row.get(1)?, row.get(2)?)?;
+        let data: Vec<u8> = row.get(3)?;
+        let algorithm: Option<String> = row.get(4)?;
+        let hash: Option<String> = row.get(5)?;
+        match algorithm.zip(hash) {
+            Some((algorithm, hash)) => {
+                report.tiles += 1;
+                if algorithm.parse::<HashAlgorithm>()?.hash(&data) != hash {
+                    report.mismatched.push(coord);
+                }
+            }
+            None => report.unhashed.push(coord),
+        }
+    }
+    let mut statement = connection.prepare(
+        "select zoom_level, tile_column, tile_row from tile_hashes
+        where not exists (select 1 from tiles where tiles.zoom_level = tile_hashes.zoom_level
+            and tiles.tile_column = tile_hashes.tile_column
+            and tiles.tile_row = tile_hashes.tile_row)
+        order by zoom_level, tile_column, tile_row;",
+    )?;
+    let missing = statement
+        .query_map((), |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect::<Result<Vec<_>, _>>()
+        .desc("Can't read the tile hashes")?;
+    report.missing = missing
+        .into_iter()
+        .map(|(zoom, x, y)| TileCoord::from_tms(zoom, x, y))
+        .collect::<Result<_, _>>()?;
+    Ok(report)
+}
@@ -0,0 +1,161 @@
+use crate::mbtile_error::MBTileError;
+use crate::tile_coord::TileCoord;
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+/// A tile image (or vector tile).
+#[derive(Debug, Clone)]
+pub struct Tile {
+    pub coord: TileCoord,
+    pub data: Vec<u8>,
+}
+
+/// A UTFGrid, `data` included.
+#[derive(Debug, Clone)]
+pub struct Grid {
+    pub coord: TileCoord,
+    pub utfgrid: Value,
+}
+
+#[derive(Debug, Clone)]
+pub enum TileEntry {
+    Tile(Tile),
+    Grid(Grid),
+}
+
+impl TileEntry {
+    pub fn coord(&self) -> TileCoord {
+        match *self {
+            TileEntry::Tile(ref tile) => tile.coord,
+            TileEntry::Grid(ref grid) => grid.coord,
+        }
+    }
+}
+
+/// Something tiles can be read from: a tiles directory, an MBTiles file...
+pub trait TileSource {
+    fn read_metadata(&mut self) -> Result<Map<String, Value>, MBTileError>;
+
+    /// Number of tiles and grids, when it can be known before visiting them.
+    fn count(&mut self) -> Result<Option<u64>, MBTileError> {
+        Ok(None)
+    }
+
+    /// Calls `visitor` on every tile and grid of the source.
+    fn visit(
+        &mut self,
+        visitor: &mut dyn FnMut(TileEntry) -> Result<(), MBTileError>,
+    ) -> Result<(), MBTileError>;
+
+    /// Calls `visitor` on the tiles and grids at `coords`.
+    ///
+    /// Sources which can look tiles up should override this scan of `visit`.
+    fn visit_tiles(
+        &mut self,
+        coords: &HashSet<TileCoord>,
+        visitor: &mut dyn FnMut(TileEntry) -> Result<(), MBTileError>,
+    ) -> Result<(), MBTileError> {
+        self.visit(&mut |entry| {
+            if coords.contains(&entry.coord()) {
+                visitor(entry)
+            } else {
+                Ok(())
+            }
+        })
+    }
+}
+
+/// Something tiles can be written to.
+pub trait TileSink {
+    fn write_metadata(&mut self, metadata: &Map<String, Value>) -> Result<(), MBTileError>;
+
+    fn write_tile(&mut self, tile: &Tile) -> Result<(), MBTileError>;
+
+    fn write_grid(&mut self, grid: &Grid) -> Result<(), MBTileError>;
+
+    /// Called once everything has been written.
+    fn finish(&mut self) -> Result<(), MBTileError> {
+        Ok(())
+    }
+}
+
+/// Counts of what went through a `copy`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CopyStats {
+    pub tiles: u64,
+    pub grids: u64,
+    /// Size of the tile data.
+    pub bytes: u64,
+}
+
+/// Where a copy is at, given to progress callbacks after each tile or grid.
+#[derive(Debug, Clone, Copy)]
+pub struct Progress {
+    pub stats: CopyStats,
+    /// Number of tiles and grids of the source, if known.
+    pub total: Option<u64>,
+    pub elapsed: Duration,
+}
+
+impl Progress {
+    pub fn processed(&self) -> u64 {
+        self.stats.tiles + self.stats.grids
+    }
+
+    pub fn bytes_per_second(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds > 0.0 {
+            self.stats.bytes as f64 / seconds
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Copies the metadata, tiles and grids of `source` into `sink`.
+pub fn copy<S, K>(source: &mut S, sink: &mut K) -> Result<CopyStats, MBTileError>
+where
+    S: TileSource + ?Sized,
+    K: TileSink + ?Sized,
+{
+    copy_with_progress(source, sink, &mut |_| {})
+}
+
+/// Like `copy`, calling `progress` after each tile and grid.
+pub fn copy_with_progress<S, K>(
+    source: &mut S,
+    sink: &mut K,
+    progress: &mut dyn FnMut(&Progress),
+) -> Result<CopyStats, MBTileError>
+where
+    S: TileSource + ?Sized,
+    K: TileSink + ?Sized,
+{
+    let start = Instant::now();
+    let total = source.count()?;
+    let mut stats = CopyStats::default();
+    let metadata = source.read_metadata()?;
+    sink.write_metadata(&metadata)?;
+    source.visit(&mut |entry| {
+        match entry {
+            TileEntry::Tile(tile) => {
+                stats.tiles += 1;
+                stats.bytes += tile.data.len() as u64;
+                sink.write_tile(&tile)?;
+            }
+            TileEntry::Grid(grid) => {
+                stats.grids += 1;
+                sink.write_grid(&grid)?;
+            }
+        }
+        progress(&Progress {
+            stats,
+            total,
+            elapsed: start.elapsed(),
+        });
+        Ok(())
+    })?;
+    sink.finish()?;
+    Ok(stats)
+}
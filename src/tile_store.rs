@@ -0,0 +1,262 @@
+use crate::mbtile_error::{ErrorKind, MBTileError, ToMBTileResult};
+use crate::mbtiles::{has_table, mbtiles_open};
+use crate::tile_coord::TileCoord;
+use crate::tile_hash::{put_tile_hash, HashAlgorithm};
+use md5::{Digest, Md5};
+use rusqlite::{Connection, OptionalExtension};
+use std::path::Path;
+
+/// Changes the tiles of an existing MBTiles file in place, whatever its schema:
+/// plain `tiles`, `grids` and `grid_data` tables, or views over the deduplicated
+/// `map`, `images`, `grid_utfgrid`, `grid_key` and `keymap` tables.
+pub(crate) struct TileStore<'c> {
+    connection: &'c Connection,
+    deduplicated: bool,
+    has_grids: bool,
+    has_grid_data: bool,
+    has_tile_hashes: bool,
+    /// Algorithm of the stored tile hashes, kept up to date.
+    hash_algorithm: Option<HashAlgorithm>,
+}
+
+impl<'c> TileStore<'c> {
+    pub(crate) fn new(connection: &'c Connection) -> Result<TileStore<'c>, MBTileError> {
+        let tiles_type: Option<String> = connection
+            .query_row(
+                "SELECT type FROM sqlite_master WHERE name = 'tiles';",
+                [],
+                |row| row.get(0),
+            )
+            .optional()
+            .desc("Can't read the schema")?;
+        let has_tile_hashes = has_table(connection, "tile_hashes")?;
+        let hash_algorithm = if has_tile_hashes {
+            let algorithm: Option<String> = connection
+                .query_row("SELECT algorithm FROM tile_hashes LIMIT 1;", [], |row| {
+                    row.get(0)
+                })
+                .optional()
+                .desc("Can't read the tile hashes")?;
+            algorithm.map(|algorithm| algorithm.parse()).transpose()?
+        } else {
+            None
+        };
+        Ok(TileStore {
+            connection,
+            deduplicated: tiles_type.as_deref() == Some("view") && has_table(connection, "map")?,
+            has_grids: has_table(connection, "grids")?,
+            has_grid_data: has_table(connection, "grid_data")?,
+            has_tile_hashes,
+            hash_algorithm,
+        })
+    }
+
+    /// Whether the tiles are a view over the `map` and `images` tables.
+    pub(crate) fn is_deduplicated(&self) -> bool {
+        self.deduplicated
+    }
+
+    pub(crate) fn get_tile(&self, coord: TileCoord) -> Result<Option<Vec<u8>>, MBTileError> {
+        self.connection
+            .prepare_cached(
+                "select tile_data from tiles
+                where zoom_level = ?1 and tile_column = ?2 and tile_row = ?3;",
+            )?
+            .query_row((coord.zoom, coord.x, coord.tms_y()?), |row| row.get(0))
+            .optional()
+            .desc(format!("Can't read tile {}", coord))
+    }
+
+    /// Writes `data` as the tile at `coord`, replacing the tile there if any.
+    ///
+    /// The deduplicated schema stores the data once under its MD5 hex digest, like mbutil.
+    pub(crate) fn put_tile(&self, coord: TileCoord, data: &[u8]) -> Result<(), MBTileError> {
+        if let Some(algorithm) = self.hash_algorithm {
+            put_tile_hash(self.connection, algorithm, coord, data)?;
+        }
+        let (zoom, x, y) = (coord.zoom, coord.x, coord.tms_y()?);
+        if self.deduplicated {
+            let tile_id = format!("{:x}", Md5::digest(data));
+            self.connection
+                .prepare_cached(
+                    "insert or ignore into images (tile_data, tile_id) values (?1, ?2);",
+                )?
+                .execute((data, &tile_id))
+                .desc(format!("Can't insert the image of tile {}", coord))?;
+            let updated = self
+                .connection
+                .prepare_cached(
+                    "update map set tile_id = ?4
+                    where zoom_level = ?1 and tile_column = ?2 and tile_row = ?3;",
+                )?
+                .execute((zoom, x, y, &tile_id))
+                .desc(format!("Can't update the map of {}", coord))?;
+            if updated == 0 {
+                self.connection
+                    .prepare_cached(
+                        "insert into map (zoom_level, tile_column, tile_row, tile_id)
+                        values (?1, ?2, ?3, ?4);",
+                    )?
+                    .execute((zoom, x, y, &tile_id))
+                    .desc(format!("Can't insert tile {}", coord))?;
+            }
+            return Ok(());
+        }
+        // files written by other tools may lack the unique index of `insert or replace`
+        let updated = self
+            .connection
+            .prepare_cached(
+                "update tiles set tile_data = ?4
+                where zoom_level = ?1 and tile_column = ?2 and tile_row = ?3;",
+            )?
+            .execute((zoom, x, y, data))
+            .desc(format!("Can't update tile {}", coord))?;
+        if updated == 0 {
+            self.connection
+                .prepare_cached(
+                    "insert into tiles (zoom_level, tile_column, tile_row, tile_data)
+                    values (?1, ?2, ?3, ?4);",
+                )?
+                .execute((zoom, x, y, data))
+                .desc(format!("Can't insert tile {}", coord))?;
+        }
+        Ok(())
+    }
+
+    /// Removes the tile and the grid at `coord`, returns how many of each were there.
+    pub(crate) fn delete(&self, coord: TileCoord) -> Result<(u64, u64), MBTileError> {
+        let key = (coord.zoom, coord.x, coord.tms_y()?);
+        if self.has_tile_hashes {
+            self.connection
+                .prepare_cached(
+                    "delete from tile_hashes
+                    where zoom_level = ?1 and tile_column = ?2 and tile_row = ?3;",
+                )?
+                .execute(key)
+                .desc(format!("Can't delete the hash of tile {}", coord))?;
+        }
+        if self.deduplicated {
+            let ids: Option<(Option<String>, Option<String>)> = self
+                .connection
+                .prepare_cached(
+                    "select tile_id, grid_id from map
+                    where zoom_level = ?1 and tile_column = ?2 and tile_row = ?3;",
+                )?
+                .query_row(key, |row| Ok((row.get(0)?, row.get(1)?)))
+                .optional()
+                .desc(format!("Can't read the map of {}", coord))?;
+            let (tile_id, grid_id) = match ids {
+                Some(ids) => ids,
+                None => return Ok((0, 0)),
+            };
+            self.connection
+                .prepare_cached(
+                    "delete from map where zoom_level = ?1 and tile_column = ?2 and tile_row = ?3;",
+                )?
+                .execute(key)
+                .desc(format!("Can't delete tile {}", coord))?;
+            return Ok((tile_id.is_some() as u64, grid_id.is_some() as u64));
+        }
+        let tiles = self
+            .connection
+            .prepare_cached(
+                "delete from tiles where zoom_level = ?1 and tile_column = ?2 and tile_row = ?3;",
+            )?
+            .execute(key)
+            .desc(format!("Can't delete tile {}", coord))?;
+        let mut grids = 0;
+        if self.has_grids {
+            grids = self
+                .connection
+                .prepare_cached(
+                    "delete from grids where zoom_level = ?1 and tile_column = ?2 and tile_row = ?3;",
+                )?
+                .execute(key)
+                .desc(format!("Can't delete grid {}", coord))?;
+        }
+        if self.has_grid_data {
+            self.connection
+                .prepare_cached(
+                    "delete from grid_data
+                    where zoom_level = ?1 and tile_column = ?2 and tile_row = ?3;",
+                )?
+                .execute(key)
+                .desc(format!("Can't delete grid data {}", coord))?;
+        }
+        Ok((tiles as u64, grids as u64))
+    }
+
+    /// Removes the images and grids no longer referenced by the `map` of a deduplicated schema.
+    pub(crate) fn remove_orphans(&self) -> Result<(), MBTileError> {
+        if !self.deduplicated {
+            return Ok(());
+        }
+        let orphans = [
+            (
+                "images",
+                "DELETE FROM images WHERE tile_id NOT IN
+                    (SELECT tile_id FROM map WHERE tile_id IS NOT NULL);",
+            ),
+            (
+                "grid_utfgrid",
+                "DELETE FROM grid_utfgrid WHERE grid_id NOT IN
+                    (SELECT grid_id FROM map WHERE grid_id IS NOT NULL);",
+            ),
+            (
+                "grid_key",
+                "DELETE FROM grid_key WHERE grid_id NOT IN
+                    (SELECT grid_id FROM map WHERE grid_id IS NOT NULL);",
+            ),
+            (
+                "keymap",
+                "DELETE FROM keymap WHERE key_name NOT IN (SELECT key_name FROM grid_key);",
+            ),
+        ];
+        for (table, query) in orphans.iter() {
+            if has_table(self.connection, table)? {
+                self.connection
+                    .execute(query, [])
+                    .desc(format!("Can't remove the unreferenced rows of {}", table))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn check_coord(coord: TileCoord) -> Result<(), MBTileError> {
+    if !coord.is_valid() {
+        return Err(MBTileError::new(
+            ErrorKind::InvalidInput,
+            format!("Tile coordinate {} is out of range", coord),
+        ));
+    }
+    Ok(())
+}
+
+/// Reads the tile at `coord` of an MBTiles file.
+pub fn tile_get<P: AsRef<Path>>(
+    input: P,
+    coord: TileCoord,
+) -> Result<Option<Vec<u8>>, MBTileError> {
+    check_coord(coord)?;
+    let connection = mbtiles_open(input.as_ref())?;
+    TileStore::new(&connection)?.get_tile(coord)
+}
+
+/// Writes `data` as the tile at `coord` of an MBTiles file, replacing the tile there if any.
+pub fn tile_put<P: AsRef<Path>>(
+    input: P,
+    coord: TileCoord,
+    data: &[u8],
+) -> Result<(), MBTileError> {
+    check_coord(coord)?;
+    let mut connection = mbtiles_open(input.as_ref())?;
+    let transaction = connection.transaction()?;
+    {
+        let store = TileStore::new(&transaction)?;
+        store.put_tile(coord, data)?;
+        store.remove_orphans()?;
+    }
+    transaction.commit()?;
+    Ok(())
+}
@@ -0,0 +1,117 @@
+use crate::mbtile_error::{ErrorKind, MBTileError, ToMBTileResult};
+use crate::mbtiles::{mbtiles_open, Scheme};
+use crate::mbtiles_metadata::read_metadata;
+use crate::tile_coord::LngLatBounds;
+use rusqlite::Connection;
+use serde_json::{Map, Value};
+use std::path::Path;
+
+/// Metadata copied as is into the TileJSON document.
+const STRING_KEYS: [&str; 6] = [
+    "name",
+    "description",
+    "version",
+    "attribution",
+    "legend",
+    "template",
+];
+
+fn metadata_str<'a>(metadata: &'a Map<String, Value>, key: &str) -> Option<&'a str> {
+    metadata.get(key).and_then(|value| value.as_str())
+}
+
+fn metadata_zoom(metadata: &Map<String, Value>, key: &str) -> Option<u64> {
+    match metadata.get(key) {
+        Some(Value::Number(number)) => number.as_u64(),
+        Some(Value::String(number)) => number.parse().ok(),
+        _ => None,
+    }
+}
+
+/// `lon,lat,zoom` into `[lon, lat, zoom]`.
+fn parse_center(center: &str) -> Option<Value> {
+    let values = center
+        .split(',')
+        .map(|v| v.trim().parse::<f64>().ok())
+        .collect::<Option<Vec<f64>>>()?;
+    match values.as_slice() {
+        [lng, lat, zoom] => Some(serde_json::json!([lng, lat, *zoom as u64])),
+        [lng, lat] => Some(serde_json::json!([lng, lat])),
+        _ => None,
+    }
+}
+
+fn zoom_range(connection: &Connection) -> Result<Option<(u64, u64)>, MBTileError> {
+    connection
+        .query_row(
+            "select min(zoom_level), max(zoom_level) from tiles;",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .map(|zooms: (Option<u64>, Option<u64>)| zooms.0.zip(zooms.1))
+        .desc("Can't read the zoom levels of the tiles")
+}
+
+/// Converts the metadata of an MBTiles file into a TileJSON 3.0 document.
+///
+/// `tiles_url` is the template of the tiles URL, like `https://example.com/{z}/{x}/{y}.png`,
+/// with rows numbered following `scheme`.
+pub fn tilejson<P: AsRef<Path>>(
+    input: P,
+    tiles_url: &str,
+    scheme: Scheme,
+) -> Result<Value, MBTileError> {
+    let scheme_name = match scheme {
+        Scheme::Xyz | Scheme::Tms => scheme.to_string(),
+        _ => {
+            return Err(MBTileError::new(
+                ErrorKind::UnsupportedScheme,
+                format!("TileJSON doesn't support the {} scheme", scheme),
+            ))
+        }
+    };
+    let connection = mbtiles_open(input.as_ref())?;
+    let metadata = read_metadata(&connection)?;
+
+    let mut tilejson = Map::new();
+    tilejson.insert("tilejson".to_owned(), Value::from("3.0.0"));
+    tilejson.insert("tiles".to_owned(), serde_json::json!([tiles_url]));
+    if let Some(vector_layers) = metadata.get("vector_layers") {
+        tilejson.insert("vector_layers".to_owned(), vector_layers.clone());
+    }
+    tilejson.insert("scheme".to_owned(), Value::from(scheme_name));
+    for key in STRING_KEYS.iter() {
+        if let Some(value) = metadata_str(&metadata, key) {
+            tilejson.insert((*key).to_owned(), Value::from(value));
+        }
+    }
+    let zooms = zoom_range(&connection)?;
+    let minzoom = metadata_zoom(&metadata, "minzoom").or(zooms.map(|z| z.0));
+    let maxzoom = metadata_zoom(&metadata, "maxzoom").or(zooms.map(|z| z.1));
+    if let Some(minzoom) = minzoom {
+        tilejson.insert("minzoom".to_owned(), Value::from(minzoom));
+    }
+    if let Some(maxzoom) = maxzoom {
+        tilejson.insert("maxzoom".to_owned(), Value::from(maxzoom));
+    }
+    if let Some(bounds) = metadata_str(&metadata, "bounds") {
+        match bounds.parse::<LngLatBounds>() {
+            Ok(bounds) => {
+                tilejson.insert(
+                    "bounds".to_owned(),
+                    serde_json::json!([bounds.west, bounds.south, bounds.east, bounds.north]),
+                );
+            }
+            Err(err) => warn!("Ignoring the bounds metadata: {}", err),
+        }
+    }
+    if let Some(center) = metadata_str(&metadata, "center") {
+        match parse_center(center) {
+            Some(center) => {
+                tilejson.insert("center".to_owned(), center);
+            }
+            None => warn!("Ignoring the center metadata {:?}", center),
+        }
+    }
+    Ok(Value::Object(tilejson))
+}
@@ -0,0 +1,311 @@
+use crate::mbtile_error::{ErrorKind, MBTileError, ToMBTileResult};
+use crate::tile_coord::TileCoord;
+use crate::tile_io::{Grid, Tile, TileSink};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+/// What to do with the UTFGrids which aren't valid.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum GridMode {
+    /// Keeps them as they are, reporting them.
+    #[default]
+    Keep,
+    /// Leaves them out.
+    Reject,
+    /// Squares the rows, blanks the characters without key and drops the data of unknown keys,
+    /// leaving out the grids which can't be repaired.
+    Repair,
+}
+
+fn corrupt(message: String) -> MBTileError {
+    MBTileError::new(ErrorKind::CorruptGrid, message)
+}
+
+/// Index in `keys` of a grid character, `None` for the characters no id encodes.
+fn decode_id(c: char) -> Option<usize> {
+    let mut code = c as u32;
+    if code < 32 || code == 34 || code == 92 {
+        return None;
+    }
+    if code >= 93 {
+        code -= 1;
+    }
+    if code >= 35 {
+        code -= 1;
+    }
+    Some((code - 32) as usize)
+}
+
+fn grid_rows(utfgrid: &Map<String, Value>) -> Result<Vec<&str>, MBTileError> {
+    match utfgrid.get("grid") {
+        Some(Value::Array(rows)) if !rows.is_empty() => rows
+            .iter()
+            .map(|row| {
+                row.as_str()
+                    .ok_or_else(|| corrupt("a grid row is not a string".to_owned()))
+            })
+            .collect(),
+        _ => Err(corrupt("no grid rows".to_owned())),
+    }
+}
+
+fn grid_keys(utfgrid: &Map<String, Value>) -> Result<Vec<&str>, MBTileError> {
+    match utfgrid.get("keys") {
+        Some(Value::Array(keys)) => keys
+            .iter()
+            .map(|key| {
+                key.as_str()
+                    .ok_or_else(|| corrupt("a key is not a string".to_owned()))
+            })
+            .collect(),
+        _ => Err(corrupt("no keys".to_owned())),
+    }
+}
+
+fn grid_data(utfgrid: &Map<String, Value>) -> Result<Option<&Map<String, Value>>, MBTileError> {
+    match utfgrid.get("data") {
+        Some(Value::Object(data)) => Ok(Some(data)),
+        Some(Value::Null) | None => Ok(None),
+        Some(_) => Err(corrupt("the data is not an object".to_owned())),
+    }
+}
+
+fn as_object(utfgrid: &Value) -> Result<&Map<String, Value>, MBTileError> {
+    utfgrid
+        .as_object()
+        .ok_or_else(|| corrupt("the grid is not an object".to_owned()))
+}
+
+/// Checks that the rows of a UTFGrid form a square, that each character encodes one of its
+/// `keys` and that its `data` is an object.
+///
+/// Returns the non-empty keys without `data` entry.
+pub fn validate_utfgrid(utfgrid: &Value) -> Result<Vec<String>, MBTileError> {
+    let utfgrid = as_object(utfgrid)?;
+    let rows = grid_rows(utfgrid)?;
+    let keys = grid_keys(utfgrid)?;
+    for (y, row) in rows.iter().enumerate() {
+        let columns = row.chars().count();
+        if columns != rows.len() {
+            return Err(corrupt(format!(
+                "row {} has {} columns instead of {}",
+                y,
+                columns,
+                rows.len()
+            )));
+        }
+        for (x, c) in row.chars().enumerate() {
+            if decode_id(c).is_none_or(|id| id >= keys.len()) {
+                return Err(corrupt(format!(
+                    "{:?} at {},{} doesn't encode one of the {} keys",
+                    c,
+                    x,
+                    y,
+                    keys.len()
+                )));
+            }
+        }
+    }
+    let data = grid_data(utfgrid)?;
+    Ok(keys
+        .iter()
+        .filter(|key| !key.is_empty() && !data.is_some_and(|data| data.contains_key(**key)))
+        .map(|key| key.to_string())
+        .collect())
+}
+
+/// Normalizes a UTFGrid: pads or truncates its rows and their count into a square, blanks the
+/// characters which don't encode one of its `keys` and drops the `data` of the keys it doesn't
+/// list.
+///
+/// The side of the square is the length most rows share, the longest of them on a tie, so a
+/// single damaged row or a few missing rows don't change the size of the grid.
+///
+/// A grid with an empty `keys` list gets the blank key. Fails when the grid has no rows,
+/// only empty ones, or no `keys` list.
+pub fn repair_utfgrid(utfgrid: &Value) -> Result<Value, MBTileError> {
+    let object = as_object(utfgrid)?;
+    let rows = grid_rows(object)?;
+    let mut keys: Vec<String> = grid_keys(object)?.into_iter().map(str::to_owned).collect();
+    if keys.is_empty() {
+        keys.push(String::new());
+    }
+    let mut lengths: BTreeMap<usize, usize> = BTreeMap::new();
+    for row in rows.iter() {
+        *lengths.entry(row.chars().count()).or_insert(0) += 1;
+    }
+    let size = lengths
+        .into_iter()
+        .max_by_key(|&(length, count)| (count, length))
+        .map_or(0, |(length, _)| length);
+    if size == 0 {
+        return Err(corrupt("the grid rows are empty".to_owned()));
+    }
+    let mut repaired_rows: Vec<Value> = rows
+        .iter()
+        .map(|row| {
+            let mut repaired: String = row
+                .chars()
+                .take(size)
+                .map(|c| match decode_id(c) {
+                    Some(id) if id < keys.len() => c,
+                    _ => ' ',
+                })
+                .collect();
+            let columns = repaired.chars().count();
+            repaired.extend(std::iter::repeat_n(' ', size - columns));
+            Value::String(repaired)
+        })
+        .collect();
+    repaired_rows.resize(size, Value::String(" ".repeat(size)));
+
+    let mut repaired = object.clone();
+    repaired.insert("grid".to_owned(), Value::Array(repaired_rows));
+    repaired.insert(
+        "keys".to_owned(),
+        Value::Array(keys.iter().cloned().map(Value::String).collect()),
+    );
+    if let Some(data) = grid_data(object)? {
+        let data = data
+            .iter()
+            .filter(|(key, _)| keys.contains(key))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        repaired.insert("data".to_owned(), Value::Object(data));
+    }
+    Ok(Value::Object(repaired))
+}
+
+/// A UTFGrid which isn't valid.
+#[derive(Debug)]
+pub struct InvalidGrid {
+    pub coord: TileCoord,
+    pub error: MBTileError,
+}
+
+/// The UTFGrids checked while importing.
+#[derive(Debug, Default)]
+pub struct GridReport {
+    /// Grids which aren't valid, kept, left out or repaired following the `GridMode`.
+    pub invalid: Vec<InvalidGrid>,
+    /// Grids left out.
+    pub rejected: Vec<TileCoord>,
+    /// Grids repaired.
+    pub repaired: Vec<TileCoord>,
+    /// The keys without `data` entry of each grid.
+    pub missing_data: BTreeMap<TileCoord, Vec<String>>,
+}
+
+enum GridAction {
+    Keep,
+    Remove,
+    Replace(Value),
+}
+
+/// Validates each UTFGrid, and counts it.
+pub(crate) struct GridChecker {
+    mode: GridMode,
+    /// Aborts on the first grid which isn't valid, unless it is kept or repaired.
+    strict: bool,
+    report: GridReport,
+}
+
+impl GridChecker {
+    pub(crate) fn new(mode: GridMode, strict: bool) -> GridChecker {
+        GridChecker {
+            mode,
+            strict,
+            report: GridReport::default(),
+        }
+    }
+
+    fn reject(&mut self, coord: TileCoord, error: MBTileError) -> Result<GridAction, MBTileError> {
+        if self.strict {
+            return Err(error)
+                .desc(format!("Invalid grid {}", coord))
+                .map_err(|err| err.with_kind(ErrorKind::CorruptGrid));
+        }
+        warn!("Leaving out grid {}: {}", coord, error);
+        self.report.rejected.push(coord);
+        self.report.invalid.push(InvalidGrid { coord, error });
+        Ok(GridAction::Remove)
+    }
+
+    fn check(&mut self, coord: TileCoord, utfgrid: &Value) -> Result<GridAction, MBTileError> {
+        let error = match validate_utfgrid(utfgrid) {
+            Ok(missing) => {
+                if !missing.is_empty() {
+                    self.report.missing_data.insert(coord, missing);
+                }
+                return Ok(GridAction::Keep);
+            }
+            Err(error) => error,
+        };
+        match self.mode {
+            GridMode::Keep => {
+                warn!("Keeping grid {}: {}", coord, error);
+                self.report.invalid.push(InvalidGrid { coord, error });
+                Ok(GridAction::Keep)
+            }
+            GridMode::Reject => self.reject(coord, error),
+            GridMode::Repair => {
+                let repaired = match repair_utfgrid(utfgrid) {
+                    Ok(repaired) => repaired,
+                    Err(error) => return self.reject(coord, error),
+                };
+                let missing = validate_utfgrid(&repaired)?;
+                if !missing.is_empty() {
+                    self.report.missing_data.insert(coord, missing);
+                }
+                self.report.repaired.push(coord);
+                self.report.invalid.push(InvalidGrid { coord, error });
+                Ok(GridAction::Replace(repaired))
+            }
+        }
+    }
+
+    pub(crate) fn into_report(self) -> GridReport {
+        self.report
+    }
+}
+
+/// Checks the grids before passing them to `sink`.
+pub(crate) struct GridCheckSink<'a> {
+    sink: &'a mut dyn TileSink,
+    checker: &'a mut GridChecker,
+}
+
+impl<'a> GridCheckSink<'a> {
+    pub(crate) fn new(
+        sink: &'a mut dyn TileSink,
+        checker: &'a mut GridChecker,
+    ) -> GridCheckSink<'a> {
+        GridCheckSink { sink, checker }
+    }
+}
+
+impl<'a> TileSink for GridCheckSink<'a> {
+    fn write_metadata(&mut self, metadata: &Map<String, Value>) -> Result<(), MBTileError> {
+        self.sink.write_metadata(metadata)
+    }
+
+    fn write_tile(&mut self, tile: &Tile) -> Result<(), MBTileError> {
+        self.sink.write_tile(tile)
+    }
+
+    fn write_grid(&mut self, grid: &Grid) -> Result<(), MBTileError> {
+        match self.checker.check(grid.coord, &grid.utfgrid)? {
+            GridAction::Keep => self.sink.write_grid(grid),
+            GridAction::Remove => Ok(()),
+            GridAction::Replace(utfgrid) => self.sink.write_grid(&Grid {
+                coord: grid.coord,
+                utfgrid,
+            }),
+        }
+    }
+
+    fn finish(&mut self) -> Result<(), MBTileError> {
+        self.sink.finish()
+    }
+}
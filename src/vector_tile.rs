@@ -0,0 +1,494 @@
+use crate::mbtile_error::{ErrorKind, MBTileError, ToMBTileResult};
+use crate::tile_io::{Grid, Tile, TileSink};
+use flate2::read::GzDecoder;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::io::prelude::*;
+
+/// Distinct values kept per attribute to compute the tilestats.
+const MAX_ATTRIBUTE_VALUES: usize = 1000;
+
+/// Values written in the tilestats of an attribute.
+const MAX_TILESTATS_VALUES: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum GeometryType {
+    Unknown,
+    Point,
+    LineString,
+    Polygon,
+}
+
+impl GeometryType {
+    fn from_proto(value: u64) -> GeometryType {
+        match value {
+            1 => GeometryType::Point,
+            2 => GeometryType::LineString,
+            3 => GeometryType::Polygon,
+            _ => GeometryType::Unknown,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match *self {
+            GeometryType::Unknown => "Unknown",
+            GeometryType::Point => "Point",
+            GeometryType::LineString => "LineString",
+            GeometryType::Polygon => "Polygon",
+        }
+    }
+}
+
+/// A feature property, integers and floats being numbers.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PropertyValue {
+    String(String),
+    Number(f64),
+    Bool(bool),
+}
+
+impl PropertyValue {
+    fn to_json(&self) -> Value {
+        match *self {
+            PropertyValue::String(ref value) => Value::from(value.as_str()),
+            PropertyValue::Number(value) if value.fract() == 0.0 && value.abs() < 1e15 => {
+                Value::from(value as i64)
+            }
+            PropertyValue::Number(value) => Value::from(value),
+            PropertyValue::Bool(value) => Value::from(value),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct VectorFeature {
+    pub geometry_type: GeometryType,
+    pub properties: Vec<(String, PropertyValue)>,
+}
+
+/// A layer of a Mapbox Vector Tile, geometries left out.
+#[derive(Debug, Clone)]
+pub struct VectorLayer {
+    pub name: String,
+    pub extent: u32,
+    pub features: Vec<VectorFeature>,
+}
+
+/// Reads the protobuf wire format.
+struct PbfReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> PbfReader<'a> {
+    fn new(data: &'a [u8]) -> PbfReader<'a> {
+        PbfReader { data, pos: 0 }
+    }
+
+    fn is_done(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn varint(&mut self) -> Result<u64, MBTileError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = *self.data.get(self.pos).ok_or_else(truncated)?;
+            self.pos += 1;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(invalid("varint longer than 64 bits"))
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], MBTileError> {
+        let end = self.pos.checked_add(len).ok_or_else(truncated)?;
+        let bytes = self.data.get(self.pos..end).ok_or_else(truncated)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    /// Field number and wire type of the next field.
+    fn key(&mut self) -> Result<(u64, u64), MBTileError> {
+        let key = self.varint()?;
+        Ok((key >> 3, key & 0x7))
+    }
+
+    fn length_delimited(&mut self) -> Result<&'a [u8], MBTileError> {
+        let len = self.varint()? as usize;
+        self.bytes(len)
+    }
+
+    fn string(&mut self) -> Result<String, MBTileError> {
+        String::from_utf8(self.length_delimited()?.to_vec())
+            .map_err(|_| invalid("string is not UTF-8"))
+    }
+
+    fn skip(&mut self, wire_type: u64) -> Result<(), MBTileError> {
+        match wire_type {
+            0 => self.varint().map(|_| ()),
+            1 => self.bytes(8).map(|_| ()),
+            2 => self.length_delimited().map(|_| ()),
+            5 => self.bytes(4).map(|_| ()),
+            _ => Err(invalid(&format!("unknown wire type {}", wire_type))),
+        }
+    }
+}
+
+fn invalid(message: &str) -> MBTileError {
+    MBTileError::new(
+        ErrorKind::InvalidInput,
+        format!("Invalid vector tile: {}", message),
+    )
+}
+
+fn truncated() -> MBTileError {
+    invalid("truncated")
+}
+
+fn decode_value(data: &[u8]) -> Result<Option<PropertyValue>, MBTileError> {
+    let mut reader = PbfReader::new(data);
+    let mut value = None;
+    while !reader.is_done() {
+        value = match reader.key()? {
+            (1, 2) => Some(PropertyValue::String(reader.string()?)),
+            (2, 5) => {
+                let bytes = reader.bytes(4)?;
+                let float = f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                Some(PropertyValue::Number(float as f64))
+            }
+            (3, 1) => {
+                let mut bytes = [0u8; 8];
+                bytes.copy_from_slice(reader.bytes(8)?);
+                Some(PropertyValue::Number(f64::from_le_bytes(bytes)))
+            }
+            (4, 0) => Some(PropertyValue::Number(reader.varint()? as i64 as f64)),
+            (5, 0) => Some(PropertyValue::Number(reader.varint()? as f64)),
+            (6, 0) => {
+                let zigzag = reader.varint()?;
+                let sint = (zigzag >> 1) as i64 ^ -((zigzag & 1) as i64);
+                Some(PropertyValue::Number(sint as f64))
+            }
+            (7, 0) => Some(PropertyValue::Bool(reader.varint()? != 0)),
+            (_, wire_type) => {
+                reader.skip(wire_type)?;
+                continue;
+            }
+        };
+    }
+    Ok(value)
+}
+
+/// Raw feature: geometry type and tags, resolved once the layer keys and values are read.
+fn decode_feature(data: &[u8]) -> Result<(GeometryType, Vec<u64>), MBTileError> {
+    let mut reader = PbfReader::new(data);
+    let mut geometry_type = GeometryType::Unknown;
+    let mut tags = Vec::new();
+    while !reader.is_done() {
+        match reader.key()? {
+            (2, 2) => {
+                let mut packed = PbfReader::new(reader.length_delimited()?);
+                while !packed.is_done() {
+                    tags.push(packed.varint()?);
+                }
+            }
+            (2, 0) => tags.push(reader.varint()?),
+            (3, 0) => geometry_type = GeometryType::from_proto(reader.varint()?),
+            (_, wire_type) => reader.skip(wire_type)?,
+        }
+    }
+    Ok((geometry_type, tags))
+}
+
+fn decode_layer(data: &[u8]) -> Result<VectorLayer, MBTileError> {
+    let mut reader = PbfReader::new(data);
+    let mut name = None;
+    let mut extent = 4096;
+    let mut raw_features = Vec::new();
+    let mut keys = Vec::new();
+    let mut values = Vec::new();
+    while !reader.is_done() {
+        match reader.key()? {
+            (1, 2) => name = Some(reader.string()?),
+            (2, 2) => raw_features.push(decode_feature(reader.length_delimited()?)?),
+            (3, 2) => keys.push(reader.string()?),
+            (4, 2) => values.push(decode_value(reader.length_delimited()?)?),
+            (5, 0) => extent = reader.varint()? as u32,
+            (_, wire_type) => reader.skip(wire_type)?,
+        }
+    }
+    let name = name.ok_or_else(|| invalid("layer without a name"))?;
+    let mut features = Vec::with_capacity(raw_features.len());
+    for (geometry_type, tags) in raw_features {
+        let mut properties = Vec::with_capacity(tags.len() / 2);
+        for tag in tags.chunks(2) {
+            let key = tag
+                .first()
+                .and_then(|&k| keys.get(k as usize))
+                .ok_or_else(|| invalid(&format!("bad key index in layer {:?}", name)))?;
+            let value = tag
+                .get(1)
+                .and_then(|&v| values.get(v as usize))
+                .ok_or_else(|| invalid(&format!("bad value index in layer {:?}", name)))?;
+            if let Some(value) = value {
+                properties.push((key.clone(), value.clone()));
+            }
+        }
+        features.push(VectorFeature {
+            geometry_type,
+            properties,
+        });
+    }
+    Ok(VectorLayer {
+        name,
+        extent,
+        features,
+    })
+}
+
+/// Decodes the layers of a Mapbox Vector Tile, gzipped or not.
+pub fn decode_vector_tile(data: &[u8]) -> Result<Vec<VectorLayer>, MBTileError> {
+    let unzipped;
+    let data = if data.starts_with(&[0x1f, 0x8b]) {
+        let mut buffer = Vec::new();
+        GzDecoder::new(data)
+            .read_to_end(&mut buffer)
+            .desc("Can't gunzip the vector tile")
+            .map_err(|err| err.with_kind(ErrorKind::InvalidInput))?;
+        unzipped = buffer;
+        &unzipped[..]
+    } else {
+        data
+    };
+    let mut reader = PbfReader::new(data);
+    let mut layers = Vec::new();
+    while !reader.is_done() {
+        match reader.key()? {
+            (3, 2) => layers.push(decode_layer(reader.length_delimited()?)?),
+            (_, wire_type) => reader.skip(wire_type)?,
+        }
+    }
+    Ok(layers)
+}
+
+#[derive(Debug, Default)]
+struct AttributeStats {
+    types: Vec<&'static str>,
+    /// Distinct values, keyed by their JSON text.
+    values: BTreeMap<String, Value>,
+    min: Option<f64>,
+    max: Option<f64>,
+}
+
+impl AttributeStats {
+    fn add(&mut self, value: &PropertyValue) {
+        let value_type = match *value {
+            PropertyValue::String(_) => "string",
+            PropertyValue::Number(_) => "number",
+            PropertyValue::Bool(_) => "boolean",
+        };
+        if !self.types.contains(&value_type) {
+            self.types.push(value_type);
+        }
+        if let PropertyValue::Number(number) = *value {
+            self.min = Some(self.min.map_or(number, |min| min.min(number)));
+            self.max = Some(self.max.map_or(number, |max| max.max(number)));
+        }
+        if self.values.len() < MAX_ATTRIBUTE_VALUES {
+            let json = value.to_json();
+            self.values.entry(json.to_string()).or_insert(json);
+        }
+    }
+
+    fn value_type(&self) -> &'static str {
+        match self.types.as_slice() {
+            [single] => single,
+            _ => "mixed",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct LayerStats {
+    minzoom: u32,
+    maxzoom: u32,
+    count: u64,
+    geometries: BTreeMap<GeometryType, u64>,
+    attributes: BTreeMap<String, AttributeStats>,
+}
+
+/// Collects the layers, fields and zoom levels of vector tiles,
+/// to build the `vector_layers` and `tilestats` metadata.
+#[derive(Debug, Default)]
+pub struct VectorLayersStats {
+    layers: BTreeMap<String, LayerStats>,
+}
+
+impl VectorLayersStats {
+    pub fn new() -> VectorLayersStats {
+        VectorLayersStats::default()
+    }
+
+    pub fn add_tile(&mut self, zoom: u32, data: &[u8]) -> Result<(), MBTileError> {
+        for layer in decode_vector_tile(data)? {
+            let stats = self.layers.entry(layer.name).or_insert_with(|| LayerStats {
+                minzoom: zoom,
+                maxzoom: zoom,
+                count: 0,
+                geometries: BTreeMap::new(),
+                attributes: BTreeMap::new(),
+            });
+            stats.minzoom = stats.minzoom.min(zoom);
+            stats.maxzoom = stats.maxzoom.max(zoom);
+            for feature in layer.features {
+                stats.count += 1;
+                *stats.geometries.entry(feature.geometry_type).or_insert(0) += 1;
+                for (key, value) in feature.properties {
+                    stats.attributes.entry(key).or_default().add(&value);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+
+    /// The `vector_layers` of the TileJSON spec.
+    pub fn vector_layers(&self) -> Value {
+        let layers = self.layers.iter().map(|(name, stats)| {
+            let fields: Map<String, Value> = stats
+                .attributes
+                .iter()
+                .map(|(key, attribute)| {
+                    let field_type = match attribute.value_type() {
+                        "string" => "String",
+                        "number" => "Number",
+                        "boolean" => "Boolean",
+                        _ => "Mixed",
+                    };
+                    (key.clone(), Value::from(field_type))
+                })
+                .collect();
+            serde_json::json!({
+                "id": name,
+                "description": "",
+                "minzoom": stats.minzoom,
+                "maxzoom": stats.maxzoom,
+                "fields": fields,
+            })
+        });
+        Value::Array(layers.collect())
+    }
+
+    /// The `tilestats` of mapbox-geostats.
+    pub fn tilestats(&self) -> Value {
+        let layers = self.layers.iter().map(|(name, stats)| {
+            // the most frequent geometry type
+            let geometry = stats
+                .geometries
+                .iter()
+                .max_by_key(|&(_, count)| *count)
+                .map_or(GeometryType::Unknown, |(geometry, _)| *geometry);
+            let attributes = stats.attributes.iter().map(|(key, attribute)| {
+                let mut attribute_stats = Map::new();
+                attribute_stats.insert("attribute".to_owned(), Value::from(key.as_str()));
+                attribute_stats.insert("count".to_owned(), Value::from(attribute.values.len()));
+                attribute_stats.insert("type".to_owned(), Value::from(attribute.value_type()));
+                attribute_stats.insert(
+                    "values".to_owned(),
+                    Value::Array(
+                        attribute
+                            .values
+                            .values()
+                            .take(MAX_TILESTATS_VALUES)
+                            .cloned()
+                            .collect(),
+                    ),
+                );
+                if let (Some(min), Some(max)) = (attribute.min, attribute.max) {
+                    attribute_stats.insert("min".to_owned(), PropertyValue::Number(min).to_json());
+                    attribute_stats.insert("max".to_owned(), PropertyValue::Number(max).to_json());
+                }
+                Value::Object(attribute_stats)
+            });
+            serde_json::json!({
+                "layer": name,
+                "count": stats.count,
+                "geometry": geometry.name(),
+                "attributeCount": stats.attributes.len(),
+                "attributes": attributes.collect::<Vec<_>>(),
+            })
+        });
+        serde_json::json!({
+            "layerCount": self.layers.len(),
+            "layers": layers.collect::<Vec<_>>(),
+        })
+    }
+}
+
+/// Whether `key` is a metadata, or a member of the `json` metadata.
+fn has_metadata(metadata: &Map<String, Value>, key: &str) -> bool {
+    metadata.contains_key(key)
+        || match metadata.get("json") {
+            Some(Value::Object(json)) => json.contains_key(key),
+            Some(Value::String(json)) => serde_json::from_str::<Map<String, Value>>(json)
+                .is_ok_and(|json| json.contains_key(key)),
+            _ => false,
+        }
+}
+
+/// Passes everything to `sink`, then completes its metadata with
+/// the `vector_layers` and `tilestats` of the tiles written.
+///
+/// Metadata already given by the source are kept.
+pub(crate) struct VectorLayersSink<'a> {
+    sink: &'a mut dyn TileSink,
+    metadata: Map<String, Value>,
+    stats: VectorLayersStats,
+}
+
+impl<'a> VectorLayersSink<'a> {
+    pub(crate) fn new(sink: &'a mut dyn TileSink) -> VectorLayersSink<'a> {
+        VectorLayersSink {
+            sink,
+            metadata: Map::new(),
+            stats: VectorLayersStats::new(),
+        }
+    }
+}
+
+impl<'a> TileSink for VectorLayersSink<'a> {
+    fn write_metadata(&mut self, metadata: &Map<String, Value>) -> Result<(), MBTileError> {
+        self.metadata = metadata.clone();
+        self.sink.write_metadata(metadata)
+    }
+
+    fn write_tile(&mut self, tile: &Tile) -> Result<(), MBTileError> {
+        if let Err(err) = self.stats.add_tile(tile.coord.zoom, &tile.data) {
+            warn!("Can't read the layers of tile {}: {}", tile.coord, err);
+        }
+        self.sink.write_tile(tile)
+    }
+
+    fn write_grid(&mut self, grid: &Grid) -> Result<(), MBTileError> {
+        self.sink.write_grid(grid)
+    }
+
+    fn finish(&mut self) -> Result<(), MBTileError> {
+        if !self.stats.is_empty() {
+            let mut metadata = self.metadata.clone();
+            if !has_metadata(&metadata, "vector_layers") {
+                metadata.insert("vector_layers".to_owned(), self.stats.vector_layers());
+            }
+            if !has_metadata(&metadata, "tilestats") {
+                metadata.insert("tilestats".to_owned(), self.stats.tilestats());
+            }
+            // the json row is written as a whole
+            self.sink.write_metadata(&metadata)?;
+        }
+        self.sink.finish()
+    }
+}
@@ -1,12 +1,25 @@
+extern crate image;
 extern crate mbutiles;
+extern crate rusqlite;
 extern crate serde_json;
 extern crate serde_json_path;
+extern crate walkdir;
 
-use mbutiles::{export, import, ImageFormat, Scheme};
+use mbutiles::{
+    build_overviews, checksum, copy, copy_mbtiles, decode_vector_tile, delete_tiles, export,
+    export_with_progress, flip_y, import, import_with_options, metadata_delete, metadata_get,
+    metadata_import, metadata_set, prune, read_tile_list, recompress, repair_utfgrid,
+    store_checksum, tile_get, tile_put, tilejson, validate_utfgrid, verify, verify_checksum,
+    DeleteReport, DirectorySink, ErrorKind, ExchangeFormat, ExistingFiles, ExportOptions,
+    GeometryType, GridMode, HashAlgorithm, ImageFormat, ImportOptions, LngLatBounds, MBTilesReader,
+    MBTilesWriter, NdjsonSink, Progress, PruneMode, RecompressOptions, Scheme, Tile, TileCoord,
+    TileEntry, TileFilter, TileRange, TileSink, TileSource, TmsCoord, CHECKSUM_METADATA,
+};
 use serde_json_path::JsonPath;
 use std::env;
 use std::fs;
 use std::fs::File;
+use std::io::Cursor;
 use std::io::Error;
 use std::path::PathBuf;
 
@@ -21,6 +34,15 @@ fn clear_data(output: &str) -> Result<(PathBuf, PathBuf), Error> {
     Ok((tests, output))
 }
 
+/// A 2x2 UTFGrid whose feature "1" is named `name`.
+fn utfgrid(name: &str) -> serde_json::Value {
+    serde_json::json!({
+        "grid": [" !", "! "],
+        "keys": ["", "1"],
+        "data": {"1": {"name": name}}
+    })
+}
+
 #[test]
 fn export_saves_tiles_and_metadata() {
     let output_name = "output_saves_tiles_and_metadata";
@@ -116,3 +138,1462 @@ fn import_tiles_to_utf8grid_mbtiles() {
     assert_eq!(path.query(&imported_json), path.query(&exported_json));
     clear_data(output_name).unwrap();
 }
+
+#[test]
+fn writer_puts_tiles_grids_and_metadata() {
+    let output_name = "writer_puts_tiles_grids_and_metadata";
+    let (_, output) = clear_data(output_name).unwrap();
+    fs::create_dir_all(&output).unwrap();
+    let mut writer = MBTilesWriter::create(output.join("written.mbtiles")).unwrap();
+    writer.set_metadata("name", "written").unwrap();
+    writer
+        .put_tile(TileCoord::from_tms(1, 0, 1).unwrap(), b"tile")
+        .unwrap();
+    let grid = utfgrid("one");
+    writer
+        .put_grid(TileCoord::from_tms(1, 0, 1).unwrap(), &grid)
+        .unwrap();
+    writer.finish().unwrap();
+    export(
+        output.join("written.mbtiles"),
+        Some(output.join("exported")),
+        Scheme::Tms,
+        ImageFormat::Png,
+        "".to_owned(),
+    )
+    .unwrap();
+    assert_eq!(
+        fs::read(output.join("exported/1/0/1.png")).unwrap(),
+        b"tile"
+    );
+    let metadata: serde_json::Value =
+        serde_json::from_reader(File::open(output.join("exported/metadata.json")).unwrap())
+            .unwrap();
+    assert_eq!(metadata["name"], "written");
+    let exported_grid: serde_json::Value =
+        serde_json::from_reader(File::open(output.join("exported/1/0/1.grid.json")).unwrap())
+            .unwrap();
+    assert_eq!(exported_grid["data"]["1"]["name"], "one");
+    clear_data(output_name).unwrap();
+}
+
+#[test]
+fn copy_mbtiles_to_mbtiles() {
+    let output_name = "copy_mbtiles_to_mbtiles";
+    let (tests, output) = clear_data(output_name).unwrap();
+    fs::create_dir_all(&output).unwrap();
+    let mut reader = MBTilesReader::open(tests.join("data/utf8grid.mbtiles")).unwrap();
+    let mut writer = MBTilesWriter::create(output.join("copy.mbtiles")).unwrap();
+    copy(&mut reader, &mut writer).unwrap();
+    writer.finish().unwrap();
+    let mut tiles = 0;
+    let mut grids = 0;
+    MBTilesReader::open(output.join("copy.mbtiles"))
+        .unwrap()
+        .visit(&mut |entry| {
+            match entry {
+                TileEntry::Tile(_) => tiles += 1,
+                TileEntry::Grid(grid) => {
+                    assert!(grid.utfgrid["data"].is_object());
+                    grids += 1
+                }
+            }
+            Ok(())
+        })
+        .unwrap();
+    assert!(tiles > 0);
+    assert!(grids > 0);
+    clear_data(output_name).unwrap();
+}
+
+#[test]
+fn tile_coord_conversions() {
+    let coord = TileCoord::new(2, 1, 0);
+    assert_eq!(coord.tms_y().unwrap(), 3);
+    let err = TileCoord::new(1, 0, 5).tms_y().unwrap_err();
+    assert_eq!(err.kind, ErrorKind::InvalidInput);
+    assert!(TileCoord::new(1, 2, 0).tms_y().is_err());
+    assert!(TmsCoord::try_from(TileCoord::new(1, 0, 5)).is_err());
+    assert_eq!(TileCoord::from_tms(2, 1, 3).unwrap(), coord);
+    assert_eq!("2/1/0".parse::<TileCoord>().unwrap(), coord);
+    assert!("2/1/4".parse::<TileCoord>().is_err());
+    let bounds = coord.bounds();
+    assert_eq!((bounds.west, bounds.east), (-90.0, 0.0));
+    assert_eq!(TileCoord::from_lng_lat(-45.0, 80.0, 2), coord);
+    assert_eq!(coord.parent(), Some(TileCoord::new(1, 0, 0)));
+    assert_eq!(coord.children()[3], TileCoord::new(3, 3, 1));
+    assert_eq!(flip_y(2, 4), None);
+    assert_eq!(flip_y(32, 0), None);
+    let err = TileCoord::from_tms(1, 0, 2).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::InvalidInput);
+    assert!(TileCoord::from_tms(32, 0, 0).is_err());
+    assert!(TileRange::world(32).is_err());
+}
+
+#[test]
+fn out_of_range_rows_are_errors() {
+    let output_name = "out_of_range_rows_are_errors";
+    let (_, output) = clear_data(output_name).unwrap();
+    fs::create_dir_all(&output).unwrap();
+    let mbtiles = output.join("corrupt.mbtiles");
+    let writer = MBTilesWriter::create(&mbtiles)
+        .unwrap()
+        .with_tile_hashes(HashAlgorithm::Md5)
+        .unwrap();
+    writer.finish().unwrap();
+    let connection = rusqlite::Connection::open(&mbtiles).unwrap();
+    connection
+        .execute(
+            "insert into tiles (zoom_level, tile_column, tile_row, tile_data) values (1, 0, 7, x'00');",
+            (),
+        )
+        .unwrap();
+    drop(connection);
+
+    let mut reader = MBTilesReader::open(&mbtiles).unwrap();
+    let err = reader.visit(&mut |_| Ok(())).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::InvalidInput);
+    assert_eq!(verify(&mbtiles).unwrap_err().kind, ErrorKind::InvalidInput);
+    assert_eq!(
+        prune(&mbtiles, PruneMode::Empty).unwrap_err().kind,
+        ErrorKind::InvalidInput
+    );
+    let err = delete_tiles(&mbtiles, &TileFilter::new(), false).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::InvalidInput);
+
+    // coordinates out of range given to the writers
+    let out_of_range = TileCoord::new(1, 0, 5);
+    let mut writer = MBTilesWriter::create(output.join("writer.mbtiles")).unwrap();
+    let err = writer.put_tile(out_of_range, b"tile").unwrap_err();
+    assert_eq!(err.kind, ErrorKind::InvalidInput);
+    let err = writer
+        .put_grid(out_of_range, &utfgrid("out of range"))
+        .unwrap_err();
+    assert_eq!(err.kind, ErrorKind::InvalidInput);
+    writer.finish().unwrap();
+    let filter = TileFilter::new().with_tiles([out_of_range].into_iter().collect());
+    let err = delete_tiles(output.join("writer.mbtiles"), &filter, false).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::InvalidInput);
+    for scheme in [Scheme::Xyz, Scheme::Tms] {
+        let mut sink = DirectorySink::create(
+            output.join(format!("tiles_{}", scheme)),
+            scheme,
+            ImageFormat::Png,
+            "".to_owned(),
+        )
+        .unwrap();
+        let tile = Tile {
+            coord: out_of_range,
+            data: solid_png([0, 0, 0, 255]),
+        };
+        let err = sink.write_tile(&tile).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::InvalidInput, "{} scheme", scheme);
+    }
+    clear_data(output_name).unwrap();
+}
+
+#[test]
+fn tile_range_from_bounds() {
+    let bounds: LngLatBounds = "-180,0,0,85".parse().unwrap();
+    let range = TileRange::from_bounds(&bounds, 1);
+    assert_eq!(range, TileRange::new(1, 0, 0, 0, 0));
+    let world = TileRange::world(2).unwrap();
+    assert_eq!(world.len(), 16);
+    let coords: Vec<TileCoord> = world.iter().collect();
+    assert_eq!(coords.len(), 16);
+    assert_eq!(coords[1], TileCoord::new(2, 1, 0));
+    assert!(world.contains(&TileCoord::new(2, 3, 3)));
+}
+
+#[test]
+fn errors_have_matchable_kinds() {
+    let output_name = "errors_have_matchable_kinds";
+    let (tests, output) = clear_data(output_name).unwrap();
+    fs::create_dir_all(&output).unwrap();
+    let err = export(
+        tests.join("data/one_tile.mbtiles"),
+        Some(output.clone()),
+        Scheme::Xyz,
+        ImageFormat::Png,
+        "".to_owned(),
+    )
+    .unwrap_err();
+    assert_eq!(err.kind, ErrorKind::OutputExists);
+    let err = export(
+        tests.join("data/missing.mbtiles"),
+        Some(output.join("missing")),
+        Scheme::Xyz,
+        ImageFormat::Png,
+        "".to_owned(),
+    )
+    .unwrap_err();
+    assert_eq!(err.kind, ErrorKind::InputNotFound);
+    let not_mbtiles = output.join("not_mbtiles.mbtiles");
+    rusqlite::Connection::open(&not_mbtiles)
+        .unwrap()
+        .execute_batch("create table metadata (name text, value text);")
+        .unwrap();
+    let err = metadata_get(&not_mbtiles, "name").unwrap_err();
+    assert_eq!(err.kind, ErrorKind::SchemaMissing);
+    let err = "1/x/0".parse::<TileCoord>().unwrap_err();
+    assert_eq!(err.kind, ErrorKind::InvalidInput);
+    assert!(std::error::Error::source(&err).is_some());
+    clear_data(output_name).unwrap();
+}
+
+#[test]
+fn import_reports_rejected_files() {
+    let output_name = "import_reports_rejected_files";
+    let (tests, output) = clear_data(output_name).unwrap();
+    export(
+        tests.join("data/one_tile.mbtiles"),
+        Some(output.join("exported")),
+        Scheme::Xyz,
+        ImageFormat::Png,
+        "".to_owned(),
+    )
+    .unwrap();
+    fs::write(output.join("exported/1/0/0.jpg"), b"jpg").unwrap();
+    fs::create_dir_all(output.join("exported/z/0")).unwrap();
+    fs::write(output.join("exported/z/0/0.png"), b"png").unwrap();
+    let report = import_with_options(
+        output.join("exported"),
+        output.join("imported.mbtiles"),
+        &ImportOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(report.tiles, 2);
+    let mut rejected: Vec<PathBuf> = report.rejected.iter().map(|r| r.path.clone()).collect();
+    rejected.sort();
+    assert_eq!(
+        rejected,
+        vec![
+            output.join("exported/1/0/0.jpg"),
+            output.join("exported/z/0/0.png")
+        ]
+    );
+    assert!(report
+        .rejected
+        .iter()
+        .all(|r| r.error.kind == ErrorKind::InvalidTilePath));
+    let err = import_with_options(
+        output.join("exported"),
+        output.join("strict.mbtiles"),
+        &ImportOptions {
+            strict: true,
+            ..ImportOptions::default()
+        },
+    )
+    .unwrap_err();
+    assert_eq!(err.kind, ErrorKind::InvalidTilePath);
+    clear_data(output_name).unwrap();
+}
+
+#[test]
+fn export_reports_progress() {
+    let output_name = "export_reports_progress";
+    let (tests, output) = clear_data(output_name).unwrap();
+    let mut last: Option<Progress> = None;
+    export_with_progress(
+        tests.join("data/utf8grid.mbtiles"),
+        Some(output.clone()),
+        &ExportOptions {
+            grid_callback: "".to_owned(),
+            existing: ExistingFiles::Fail,
+            ..ExportOptions::default()
+        },
+        &TileFilter::default(),
+        &mut |progress| last = Some(*progress),
+    )
+    .unwrap();
+    let last = last.unwrap();
+    assert_eq!(last.total, Some(2));
+    assert_eq!(last.processed(), 2);
+    assert_eq!((last.stats.tiles, last.stats.grids), (1, 1));
+    assert!(last.stats.bytes > 0);
+    clear_data(output_name).unwrap();
+}
+
+#[test]
+fn metadata_get_set_delete_import() {
+    let output_name = "metadata_get_set_delete_import";
+    let (tests, output) = clear_data(output_name).unwrap();
+    fs::create_dir_all(&output).unwrap();
+    let mbtiles = output.join("one_tile.mbtiles");
+    fs::copy(tests.join("data/one_tile.mbtiles"), &mbtiles).unwrap();
+    assert_eq!(
+        metadata_get(&mbtiles, "name").unwrap(),
+        Some("shadowplay".to_owned())
+    );
+    metadata_set(&mbtiles, "name", "renamed").unwrap();
+    assert_eq!(
+        metadata_get(&mbtiles, "name").unwrap(),
+        Some("renamed".to_owned())
+    );
+    assert!(metadata_delete(&mbtiles, "name").unwrap());
+    assert!(!metadata_delete(&mbtiles, "name").unwrap());
+    assert_eq!(metadata_get(&mbtiles, "name").unwrap(), None);
+    fs::write(
+        output.join("metadata.json"),
+        r#"{"name": "imported", "attribution": "me"}"#,
+    )
+    .unwrap();
+    metadata_import(&mbtiles, output.join("metadata.json")).unwrap();
+    assert_eq!(
+        metadata_get(&mbtiles, "attribution").unwrap(),
+        Some("me".to_owned())
+    );
+    assert_eq!(
+        metadata_get(&mbtiles, "version").unwrap(),
+        Some("1.0.0".to_owned())
+    );
+    clear_data(output_name).unwrap();
+}
+
+#[test]
+fn import_and_export_structured_metadata() {
+    let output_name = "import_and_export_structured_metadata";
+    let (tests, output) = clear_data(output_name).unwrap();
+    export(
+        tests.join("data/one_tile.mbtiles"),
+        Some(output.join("exported")),
+        Scheme::Xyz,
+        ImageFormat::Png,
+        "".to_owned(),
+    )
+    .unwrap();
+    let metadata = serde_json::json!({
+        "name": "structured",
+        "minzoom": 0,
+        "maxzoom": 1,
+        "vector_layers": [{"id": "water", "fields": {}}],
+        "json": {"custom": {"nested": true}},
+        "tags": ["a", "b"],
+        "bounds": [-10, -10, 10.5, 10],
+        "center": [0, 0, 1],
+        "description": "[beta] {draft}"
+    });
+    fs::write(output.join("exported/metadata.json"), metadata.to_string()).unwrap();
+    import_with_options(
+        output.join("exported"),
+        output.join("imported.mbtiles"),
+        &ImportOptions::default(),
+    )
+    .unwrap();
+    let json_row = metadata_get(output.join("imported.mbtiles"), "json")
+        .unwrap()
+        .unwrap();
+    let json_row: serde_json::Value = serde_json::from_str(&json_row).unwrap();
+    assert_eq!(json_row["vector_layers"][0]["id"], "water");
+    assert_eq!(json_row["tags"], serde_json::json!(["a", "b"]));
+    assert_eq!(
+        metadata_get(output.join("imported.mbtiles"), "maxzoom").unwrap(),
+        Some("1".to_owned())
+    );
+    assert_eq!(
+        metadata_get(output.join("imported.mbtiles"), "bounds").unwrap(),
+        Some("-10,-10,10.5,10".to_owned())
+    );
+    let document = tilejson(output.join("imported.mbtiles"), "", Scheme::Xyz).unwrap();
+    assert_eq!(
+        document["bounds"],
+        serde_json::json!([-10.0, -10.0, 10.5, 10.0])
+    );
+    assert_eq!(document["center"], serde_json::json!([0.0, 0.0, 1]));
+    export(
+        output.join("imported.mbtiles"),
+        Some(output.join("reexported")),
+        Scheme::Xyz,
+        ImageFormat::Png,
+        "".to_owned(),
+    )
+    .unwrap();
+    let reexported: serde_json::Value =
+        serde_json::from_reader(File::open(output.join("reexported/metadata.json")).unwrap())
+            .unwrap();
+    let mut expected = metadata.clone();
+    // the members of the json row come back at the top level, bounds and center as in the spec
+    let expected = expected.as_object_mut().unwrap();
+    expected.remove("json");
+    expected.insert("custom".to_owned(), serde_json::json!({"nested": true}));
+    expected.insert("bounds".to_owned(), serde_json::json!("-10,-10,10.5,10"));
+    expected.insert("center".to_owned(), serde_json::json!("0,0,1"));
+    assert_eq!(reexported, serde_json::Value::Object(expected.clone()));
+
+    fs::write(output.join("bounds.json"), r#"{"bounds": ["west"]}"#).unwrap();
+    let err =
+        metadata_import(output.join("imported.mbtiles"), output.join("bounds.json")).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::InvalidInput);
+    clear_data(output_name).unwrap();
+}
+
+#[test]
+fn tilejson_from_metadata() {
+    let document = tilejson(
+        "tests/data/one_tile.mbtiles",
+        "https://example.com/{z}/{x}/{y}.png",
+        Scheme::Xyz,
+    )
+    .unwrap();
+    assert_eq!(document["tilejson"], "3.0.0");
+    assert_eq!(
+        document["tiles"],
+        serde_json::json!(["https://example.com/{z}/{x}/{y}.png"])
+    );
+    assert_eq!(document["scheme"], "xyz");
+    assert_eq!(document["name"], "shadowplay");
+    assert_eq!(document["minzoom"], 0);
+    assert_eq!(document["maxzoom"], 1);
+    assert_eq!(
+        document["bounds"],
+        serde_json::json!([-139.9219, 53.3309, -75.2344, 76.6798])
+    );
+    let err = tilejson("tests/data/one_tile.mbtiles", "", Scheme::Wms).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::UnsupportedScheme);
+}
+
+#[test]
+fn import_pbf_generates_vector_layers() {
+    let output_name = "import_pbf_generates_vector_layers";
+    let (tests, output) = clear_data(output_name).unwrap();
+    fs::create_dir_all(&output).unwrap();
+    let layers =
+        decode_vector_tile(&fs::read(tests.join("data/vector/1/1/0.pbf")).unwrap()).unwrap();
+    assert_eq!(layers.len(), 2);
+    assert_eq!(layers[0].name, "places");
+    assert_eq!(layers[0].features.len(), 2);
+    assert_eq!(layers[0].features[0].geometry_type, GeometryType::Point);
+
+    import_with_options(
+        tests.join("data/vector"),
+        output.join("vector.mbtiles"),
+        &ImportOptions {
+            image_format: ImageFormat::Pbf,
+            ..ImportOptions::default()
+        },
+    )
+    .unwrap();
+    let json_row = metadata_get(output.join("vector.mbtiles"), "json")
+        .unwrap()
+        .unwrap();
+    let json_row: serde_json::Value = serde_json::from_str(&json_row).unwrap();
+    let vector_layers = json_row["vector_layers"].as_array().unwrap();
+    let ids: Vec<&str> = vector_layers
+        .iter()
+        .map(|layer| layer["id"].as_str().unwrap())
+        .collect();
+    assert_eq!(ids, vec!["places", "roads", "water"]);
+    assert_eq!(
+        vector_layers[0]["fields"],
+        serde_json::json!({
+            "capital": "Boolean",
+            "name": "String",
+            "population": "Number",
+            "rank": "Number"
+        })
+    );
+    assert_eq!(vector_layers[0]["minzoom"], 0);
+    assert_eq!(vector_layers[0]["maxzoom"], 1);
+    assert_eq!(vector_layers[1]["minzoom"], 1);
+    assert_eq!(vector_layers[2]["maxzoom"], 0);
+
+    let tilestats = &json_row["tilestats"];
+    assert_eq!(tilestats["layerCount"], 3);
+    assert_eq!(tilestats["layers"][0]["count"], 3);
+    assert_eq!(tilestats["layers"][0]["geometry"], "Point");
+    assert_eq!(tilestats["layers"][2]["geometry"], "Polygon");
+    let population = &tilestats["layers"][0]["attributes"][2];
+    assert_eq!(population["attribute"], "population");
+    assert_eq!(population["min"], 500000);
+    assert_eq!(population["max"], 2100000);
+    assert_eq!(
+        metadata_get(output.join("vector.mbtiles"), "name").unwrap(),
+        Some("vector".to_owned())
+    );
+    clear_data(output_name).unwrap();
+}
+
+fn solid_png(color: [u8; 4]) -> Vec<u8> {
+    let mut data = Vec::new();
+    image::RgbaImage::from_pixel(256, 256, image::Rgba(color))
+        .write_to(&mut Cursor::new(&mut data), image::ImageFormat::Png)
+        .unwrap();
+    data
+}
+
+#[test]
+fn build_overviews_composites_children() {
+    let output_name = "build_overviews_composites_children";
+    let (_, output) = clear_data(output_name).unwrap();
+    fs::create_dir_all(&output).unwrap();
+    let mbtiles = output.join("overviews.mbtiles");
+    let mut writer = MBTilesWriter::create(&mbtiles).unwrap();
+    writer.set_metadata("minzoom", "2").unwrap();
+    writer
+        .put_tile(TileCoord::new(2, 0, 0), &solid_png([255, 0, 0, 255]))
+        .unwrap();
+    writer
+        .put_tile(TileCoord::new(2, 1, 0), &solid_png([0, 255, 0, 255]))
+        .unwrap();
+    writer
+        .put_tile(TileCoord::new(2, 0, 1), &solid_png([0, 0, 255, 255]))
+        .unwrap();
+    writer.finish().unwrap();
+
+    let report = build_overviews(&mbtiles, 0).unwrap();
+    assert_eq!(report.tiles(), 2);
+    assert_eq!(report.created.get(&1), Some(&1));
+    assert_eq!(report.created.get(&0), Some(&1));
+    assert_eq!(
+        metadata_get(&mbtiles, "minzoom").unwrap(),
+        Some("0".to_owned())
+    );
+
+    let mut tiles = Vec::new();
+    MBTilesReader::open(&mbtiles)
+        .unwrap()
+        .visit(&mut |entry| {
+            if let TileEntry::Tile(tile) = entry {
+                tiles.push(tile);
+            }
+            Ok(())
+        })
+        .unwrap();
+    let overview = tiles
+        .iter()
+        .find(|tile| tile.coord == TileCoord::new(1, 0, 0))
+        .unwrap();
+    let overview = image::load_from_memory(&overview.data).unwrap().to_rgba8();
+    assert_eq!(overview.dimensions(), (256, 256));
+    assert_eq!(overview.get_pixel(10, 10).0, [255, 0, 0, 255]);
+    assert_eq!(overview.get_pixel(200, 10).0, [0, 255, 0, 255]);
+    assert_eq!(overview.get_pixel(10, 200).0, [0, 0, 255, 255]);
+    assert_eq!(overview.get_pixel(200, 200).0[3], 0);
+    assert!(tiles
+        .iter()
+        .any(|tile| tile.coord == TileCoord::new(0, 0, 0)));
+
+    // existing tiles are kept
+    assert_eq!(build_overviews(&mbtiles, 0).unwrap().tiles(), 0);
+    clear_data(output_name).unwrap();
+}
+
+#[test]
+fn recompress_converts_and_optimizes_tiles() {
+    let output_name = "recompress_converts_and_optimizes_tiles";
+    let (_, output) = clear_data(output_name).unwrap();
+    fs::create_dir_all(&output).unwrap();
+    let mbtiles = output.join("png.mbtiles");
+    let mut writer = MBTilesWriter::create(&mbtiles).unwrap();
+    writer.set_metadata("format", "png").unwrap();
+    writer
+        .put_tile(TileCoord::new(0, 0, 0), &solid_png([255, 0, 0, 255]))
+        .unwrap();
+    writer
+        .put_tile(TileCoord::new(1, 1, 0), &solid_png([0, 0, 0, 0]))
+        .unwrap();
+    writer.finish().unwrap();
+
+    let jpg = output.join("jpg.mbtiles");
+    let report = recompress(
+        &mbtiles,
+        &jpg,
+        &RecompressOptions {
+            image_format: ImageFormat::Jpg,
+            quality: 50,
+        },
+    )
+    .unwrap();
+    assert_eq!(report.tiles, 2);
+    assert_eq!(report.recompressed, 2);
+    assert_eq!(
+        metadata_get(&jpg, "format").unwrap(),
+        Some("jpg".to_owned())
+    );
+    MBTilesReader::open(&jpg)
+        .unwrap()
+        .visit(&mut |entry| {
+            if let TileEntry::Tile(tile) = entry {
+                assert_eq!(
+                    image::guess_format(&tile.data).unwrap(),
+                    image::ImageFormat::Jpeg
+                );
+            }
+            Ok(())
+        })
+        .unwrap();
+
+    let optimized = output.join("optimized.mbtiles");
+    let report = recompress(
+        &mbtiles,
+        &optimized,
+        &RecompressOptions {
+            image_format: ImageFormat::Png,
+            quality: 85,
+        },
+    )
+    .unwrap();
+    assert_eq!(report.recompressed + report.kept, 2);
+    assert!(report.bytes_after <= report.bytes_before);
+
+    let err = recompress(
+        &mbtiles,
+        &output.join("pbf.mbtiles"),
+        &RecompressOptions {
+            image_format: ImageFormat::Pbf,
+            quality: 85,
+        },
+    )
+    .unwrap_err();
+    assert_eq!(err.kind, ErrorKind::InvalidInput);
+    clear_data(output_name).unwrap();
+}
+
+#[test]
+fn prune_empty_and_solid_tiles() {
+    let output_name = "prune_empty_and_solid_tiles";
+    let (_, output) = clear_data(output_name).unwrap();
+    let tiles = output.join("tiles");
+    fs::create_dir_all(tiles.join("1/0")).unwrap();
+    fs::create_dir_all(tiles.join("1/1")).unwrap();
+    fs::write(tiles.join("1/0/0.png"), solid_png([0, 0, 0, 0])).unwrap();
+    let mut varied = image::RgbaImage::from_pixel(256, 256, image::Rgba([0, 0, 255, 255]));
+    varied.put_pixel(0, 0, image::Rgba([255, 255, 255, 255]));
+    let mut varied_png = Vec::new();
+    varied
+        .write_to(&mut Cursor::new(&mut varied_png), image::ImageFormat::Png)
+        .unwrap();
+    fs::write(tiles.join("1/0/1.png"), varied_png).unwrap();
+    fs::write(tiles.join("1/1/0.png"), solid_png([0, 0, 255, 255])).unwrap();
+    // same color, different data
+    let mut solid_rgb = Vec::new();
+    image::RgbImage::from_pixel(256, 256, image::Rgb([0, 0, 255]))
+        .write_to(&mut Cursor::new(&mut solid_rgb), image::ImageFormat::Png)
+        .unwrap();
+    fs::write(tiles.join("1/1/1.png"), solid_rgb).unwrap();
+    let grid = utfgrid("one");
+    fs::write(tiles.join("1/0/1.grid.json"), grid.to_string()).unwrap();
+
+    let mbtiles = output.join("pruned.mbtiles");
+    let report = import_with_options(
+        &tiles,
+        &mbtiles,
+        &ImportOptions {
+            prune: Some(PruneMode::Dedupe),
+            ..ImportOptions::default()
+        },
+    )
+    .unwrap();
+    assert_eq!(report.tiles, 3);
+    assert_eq!(report.pruned.removed.get(&1), Some(&1));
+    assert_eq!(report.pruned.deduplicated.get(&1), Some(&1));
+    // the two blue tiles share their data
+    let images: u32 = rusqlite::Connection::open(&mbtiles)
+        .unwrap()
+        .query_row("select count(*) from images;", (), |row| row.get(0))
+        .unwrap();
+    assert_eq!(images, 2);
+    let mut grids = Vec::new();
+    MBTilesReader::open(&mbtiles)
+        .unwrap()
+        .visit(&mut |entry| {
+            if let TileEntry::Grid(grid) = entry {
+                grids.push(grid.utfgrid);
+            }
+            Ok(())
+        })
+        .unwrap();
+    assert_eq!(grids, vec![grid]);
+    assert!(prune(&mbtiles, PruneMode::Dedupe).is_ok());
+
+    let report = prune(&mbtiles, PruneMode::Solid).unwrap();
+    assert_eq!(report.removed.get(&1), Some(&2));
+    let mut left = Vec::new();
+    MBTilesReader::open(&mbtiles)
+        .unwrap()
+        .visit(&mut |entry| {
+            if let TileEntry::Tile(tile) = entry {
+                left.push(tile.coord);
+            }
+            Ok(())
+        })
+        .unwrap();
+    assert_eq!(left, vec![TileCoord::new(1, 0, 1)]);
+
+    let plain = output.join("plain.mbtiles");
+    import_with_options(&tiles, &plain, &ImportOptions::default()).unwrap();
+    let err = prune(&plain, PruneMode::Dedupe).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::SchemaMissing);
+    clear_data(output_name).unwrap();
+}
+
+#[test]
+fn copy_mbtiles_with_filters() {
+    let output_name = "copy_mbtiles_with_filters";
+    let (_, output) = clear_data(output_name).unwrap();
+    fs::create_dir_all(&output).unwrap();
+    let mbtiles = output.join("world.mbtiles");
+    let mut writer = MBTilesWriter::create(&mbtiles).unwrap();
+    writer.set_metadata("name", "world").unwrap();
+    writer.set_metadata("bounds", "-180,-85,180,85").unwrap();
+    for coord in [
+        TileCoord::new(1, 0, 0),
+        TileCoord::new(1, 1, 0),
+        TileCoord::new(2, 0, 0),
+        TileCoord::new(2, 3, 3),
+    ] {
+        writer
+            .put_tile(coord, coord.to_string().as_bytes())
+            .unwrap();
+    }
+    let grid = utfgrid("one");
+    writer.put_grid(TileCoord::new(2, 0, 0), &grid).unwrap();
+    writer.put_grid(TileCoord::new(2, 3, 3), &grid).unwrap();
+    writer.finish().unwrap();
+
+    let extract = output.join("extract.mbtiles");
+    let filter = TileFilter::new().with_bounds(LngLatBounds::new(-180.0, 0.0, 0.0, 85.0));
+    let stats = copy_mbtiles(&mbtiles, &extract, &filter).unwrap();
+    assert_eq!((stats.tiles, stats.grids), (2, 1));
+    let mut entries = Vec::new();
+    MBTilesReader::open(&extract)
+        .unwrap()
+        .visit(&mut |entry| {
+            entries.push(entry);
+            Ok(())
+        })
+        .unwrap();
+    match entries[2] {
+        TileEntry::Grid(ref grid) => {
+            assert_eq!(grid.coord, TileCoord::new(2, 0, 0));
+            assert_eq!(grid.utfgrid["data"]["1"]["name"], "one");
+        }
+        _ => panic!("no grid"),
+    }
+    let bounds: LngLatBounds = metadata_get(&extract, "bounds")
+        .unwrap()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert_eq!(
+        (bounds.west, bounds.east, bounds.north),
+        (-180.0, -90.0, 85.0)
+    );
+    assert!((bounds.south - 66.51).abs() < 0.01);
+    assert_eq!(
+        metadata_get(&extract, "minzoom").unwrap(),
+        Some("1".to_owned())
+    );
+    assert_eq!(
+        metadata_get(&extract, "maxzoom").unwrap(),
+        Some("2".to_owned())
+    );
+    assert_eq!(
+        metadata_get(&extract, "name").unwrap(),
+        Some("world".to_owned())
+    );
+
+    let list = output.join("tiles.txt");
+    fs::write(&list, "# invalidated\n2/3/3\n1/0/0\n\n").unwrap();
+    let filter = TileFilter::new()
+        .with_zoom_range(Some(2), None)
+        .with_tiles(read_tile_list(&list, Scheme::Xyz).unwrap());
+    let stats = copy_mbtiles(&mbtiles, &output.join("list.mbtiles"), &filter).unwrap();
+    assert_eq!((stats.tiles, stats.grids), (1, 1));
+    clear_data(output_name).unwrap();
+}
+
+#[test]
+fn export_and_delete_tiles_from_list() {
+    let output_name = "export_and_delete_tiles_from_list";
+    let (_, output) = clear_data(output_name).unwrap();
+    fs::create_dir_all(&output).unwrap();
+    let mbtiles = output.join("tiles.mbtiles");
+    let mut writer = MBTilesWriter::create(&mbtiles).unwrap();
+    let grid = utfgrid("one");
+    for coord in [
+        TileCoord::new(1, 0, 0),
+        TileCoord::new(1, 1, 0),
+        TileCoord::new(1, 1, 1),
+    ] {
+        writer.put_tile(coord, b"tile").unwrap();
+        writer.put_grid(coord, &grid).unwrap();
+    }
+    writer.finish().unwrap();
+    let list = output.join("invalidated.txt");
+    fs::write(&list, "1/1/0\n1/1/1\n").unwrap();
+    let tiles = read_tile_list(&list, Scheme::Xyz).unwrap();
+    let tms_list = output.join("invalidated_tms.txt");
+    fs::write(&tms_list, "1/1/1\n1/1/0\n").unwrap();
+    assert_eq!(read_tile_list(&tms_list, Scheme::Tms).unwrap(), tiles);
+
+    let mut looked_up = Vec::new();
+    MBTilesReader::open(&mbtiles)
+        .unwrap()
+        .visit_tiles(&tiles, &mut |entry| {
+            if let TileEntry::Grid(ref grid) = entry {
+                assert_eq!(grid.utfgrid["data"]["1"]["name"], "one");
+            }
+            looked_up.push(entry.coord());
+            Ok(())
+        })
+        .unwrap();
+    assert_eq!(looked_up.len(), 4);
+    assert!(looked_up.iter().all(|coord| tiles.contains(coord)));
+
+    export_with_progress(
+        &mbtiles,
+        Some(&output.join("exported")),
+        &ExportOptions {
+            grid_callback: "".to_owned(),
+            existing: ExistingFiles::Fail,
+            ..ExportOptions::default()
+        },
+        &TileFilter::new().with_tiles(tiles.clone()),
+        &mut |_| {},
+    )
+    .unwrap();
+    assert!(!output.join("exported/1/0/0.png").exists());
+    assert!(output.join("exported/1/1/0.png").exists());
+    assert!(output.join("exported/1/1/1.grid.json").exists());
+
+    let report = delete_tiles(&mbtiles, &TileFilter::new().with_tiles(tiles), false).unwrap();
+    assert_eq!(report, DeleteReport { tiles: 2, grids: 2 });
+    let mut left = Vec::new();
+    MBTilesReader::open(&mbtiles)
+        .unwrap()
+        .visit(&mut |entry| {
+            match entry {
+                TileEntry::Tile(tile) => left.push(("tile", tile.coord)),
+                TileEntry::Grid(grid) => {
+                    assert_eq!(grid.utfgrid["data"]["1"]["name"], "one");
+                    left.push(("grid", grid.coord))
+                }
+            }
+            Ok(())
+        })
+        .unwrap();
+    assert_eq!(
+        left,
+        vec![
+            ("tile", TileCoord::new(1, 0, 0)),
+            ("grid", TileCoord::new(1, 0, 0))
+        ]
+    );
+    clear_data(output_name).unwrap();
+}
+
+#[test]
+fn delete_tiles_by_zoom_and_bbox() {
+    let output_name = "delete_tiles_by_zoom_and_bbox";
+    let (tests, output) = clear_data(output_name).unwrap();
+    fs::create_dir_all(&output).unwrap();
+    let mbtiles = output.join("tiles.mbtiles");
+    let mut writer = MBTilesWriter::create(&mbtiles).unwrap();
+    for coord in [
+        TileCoord::new(1, 0, 0),
+        TileCoord::new(1, 1, 1),
+        TileCoord::new(2, 0, 0),
+        TileCoord::new(2, 3, 3),
+        TileCoord::new(3, 7, 7),
+    ] {
+        writer.put_tile(coord, b"tile").unwrap();
+    }
+    writer.finish().unwrap();
+    let coords = |path: &PathBuf| {
+        let mut coords = Vec::new();
+        MBTilesReader::open(path)
+            .unwrap()
+            .visit(&mut |entry| {
+                if let TileEntry::Tile(tile) = entry {
+                    coords.push(tile.coord);
+                }
+                Ok(())
+            })
+            .unwrap();
+        coords.sort();
+        coords
+    };
+
+    // south-east quarter, from zoom 2
+    let filter = TileFilter::new()
+        .with_zoom_range(Some(2), None)
+        .with_bounds(LngLatBounds::new(0.0, -85.0, 180.0, 0.0));
+    let report = delete_tiles(&mbtiles, &filter, true).unwrap();
+    assert_eq!(report, DeleteReport { tiles: 2, grids: 0 });
+    assert_eq!(
+        coords(&mbtiles),
+        vec![
+            TileCoord::new(1, 0, 0),
+            TileCoord::new(1, 1, 1),
+            TileCoord::new(2, 0, 0)
+        ]
+    );
+    let filter = TileFilter::new().with_zoom_range(None, Some(1));
+    assert_eq!(delete_tiles(&mbtiles, &filter, false).unwrap().tiles, 2);
+    assert_eq!(coords(&mbtiles), vec![TileCoord::new(2, 0, 0)]);
+
+    // tiles and grids are views of the deduplicated schema
+    let deduplicated = output.join("utf8grid.mbtiles");
+    fs::copy(tests.join("data/utf8grid.mbtiles"), &deduplicated).unwrap();
+    let filter = TileFilter::new().with_zoom_range(Some(0), Some(0));
+    let report = delete_tiles(&deduplicated, &filter, true).unwrap();
+    assert_eq!(report, DeleteReport { tiles: 1, grids: 1 });
+    assert!(coords(&deduplicated).is_empty());
+    clear_data(output_name).unwrap();
+}
+
+#[test]
+fn tile_get_and_put() {
+    let output_name = "tile_get_and_put";
+    let (tests, output) = clear_data(output_name).unwrap();
+    fs::create_dir_all(&output).unwrap();
+    let mbtiles = output.join("tiles.mbtiles");
+    let mut writer = MBTilesWriter::create(&mbtiles).unwrap();
+    writer.put_tile(TileCoord::new(1, 0, 0), b"old").unwrap();
+    writer.finish().unwrap();
+
+    let coord = TileCoord::new(1, 0, 0);
+    assert_eq!(tile_get(&mbtiles, coord).unwrap(), Some(b"old".to_vec()));
+    tile_put(&mbtiles, coord, b"new").unwrap();
+    tile_put(&mbtiles, TileCoord::new(1, 1, 1), b"added").unwrap();
+    assert_eq!(tile_get(&mbtiles, coord).unwrap(), Some(b"new".to_vec()));
+    assert_eq!(
+        tile_get(&mbtiles, TileCoord::from_tms(1, 1, 0).unwrap()).unwrap(),
+        Some(b"added".to_vec())
+    );
+    assert_eq!(tile_get(&mbtiles, TileCoord::new(1, 0, 1)).unwrap(), None);
+    let err = tile_put(&mbtiles, TileCoord::new(1, 2, 0), b"out").unwrap_err();
+    assert_eq!(err.kind, ErrorKind::InvalidInput);
+    let err = tile_get(&mbtiles, TileCoord::new(1, 2, 0)).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::InvalidInput);
+
+    // tiles is a view of the deduplicated schema
+    let deduplicated = output.join("one_tile.mbtiles");
+    fs::copy(tests.join("data/one_tile.mbtiles"), &deduplicated).unwrap();
+    tile_put(&deduplicated, TileCoord::new(0, 0, 0), b"replaced").unwrap();
+    tile_put(&deduplicated, TileCoord::new(1, 1, 1), b"replaced").unwrap();
+    assert_eq!(
+        tile_get(&deduplicated, TileCoord::new(0, 0, 0)).unwrap(),
+        Some(b"replaced".to_vec())
+    );
+    let mut reader = MBTilesReader::open(&deduplicated).unwrap();
+    assert_eq!(reader.count().unwrap(), Some(3));
+    clear_data(output_name).unwrap();
+}
+
+#[test]
+fn checksum_ignores_storage_order() {
+    let output_name = "checksum_ignores_storage_order";
+    let (_, output) = clear_data(output_name).unwrap();
+    fs::create_dir_all(&output).unwrap();
+    let coords = [
+        TileCoord::new(0, 0, 0),
+        TileCoord::new(1, 0, 0),
+        TileCoord::new(1, 1, 1),
+    ];
+    let create = |name: &str, reversed: bool| {
+        let mbtiles = output.join(name);
+        let mut writer = MBTilesWriter::create(&mbtiles).unwrap();
+        writer.set_metadata("name", "checksum").unwrap();
+        let mut ordered = coords.to_vec();
+        if reversed {
+            ordered.reverse();
+        }
+        for coord in ordered {
+            writer
+                .put_tile(coord, coord.to_string().as_bytes())
+                .unwrap();
+        }
+        writer.finish().unwrap();
+        mbtiles
+    };
+    let first = create("first.mbtiles", false);
+    let second = create("second.mbtiles", true);
+    let stored = store_checksum(&first).unwrap();
+    assert_eq!(checksum(&second).unwrap(), stored);
+    assert_eq!(stored.zooms.len(), 2);
+    assert_eq!(verify_checksum(&first).unwrap(), stored);
+    assert_eq!(
+        metadata_get(&first, CHECKSUM_METADATA).unwrap(),
+        Some(stored.hash.clone())
+    );
+    assert_eq!(metadata_get(&first, "agg_tiles_hash").unwrap(), None);
+    assert_eq!(
+        verify_checksum(&second).unwrap_err().kind,
+        ErrorKind::InvalidInput
+    );
+
+    tile_put(&first, TileCoord::new(1, 1, 1), b"changed").unwrap();
+    assert_eq!(
+        verify_checksum(&first).unwrap_err().kind,
+        ErrorKind::ChecksumMismatch
+    );
+    let changed = checksum(&first).unwrap();
+    assert_eq!(changed.zooms[&0], stored.zooms[&0]);
+    assert_ne!(changed.zooms[&1], stored.zooms[&1]);
+    metadata_set(&second, "name", "renamed").unwrap();
+    let renamed = checksum(&second).unwrap();
+    assert_ne!(renamed.hash, stored.hash);
+    assert_eq!(renamed.zooms, stored.zooms);
+    clear_data(output_name).unwrap();
+}
+
+#[test]
+fn verify_tile_hashes() {
+    let output_name = "verify_tile_hashes";
+    let (tests, output) = clear_data(output_name).unwrap();
+    export(
+        tests.join("data/one_tile.mbtiles"),
+        Some(output.join("exported")),
+        Scheme::Xyz,
+        ImageFormat::Png,
+        "".to_owned(),
+    )
+    .unwrap();
+    let mbtiles = output.join("hashed.mbtiles");
+    let options = ImportOptions {
+        tile_hashes: Some(HashAlgorithm::Xxh3),
+        ..ImportOptions::default()
+    };
+    import_with_options(output.join("exported"), mbtiles.clone(), &options).unwrap();
+    let report = verify(&mbtiles).unwrap();
+    assert_eq!(report.tiles, 2);
+    assert!(report.is_ok());
+
+    // kept up to date by the commands changing tiles
+    tile_put(&mbtiles, TileCoord::new(1, 1, 1), b"put").unwrap();
+    let filter = TileFilter::new().with_tiles([TileCoord::new(0, 0, 0)].into_iter().collect());
+    delete_tiles(&mbtiles, &filter, false).unwrap();
+    let report = verify(&mbtiles).unwrap();
+    assert_eq!(report.tiles, 2);
+    assert!(report.is_ok());
+
+    let connection = rusqlite::Connection::open(&mbtiles).unwrap();
+    connection
+        .execute_batch(
+            "update tiles set tile_data = x'00' where zoom_level = 1 and tile_column = 1;
+            insert into tiles values (2, 0, 0, x'00');
+            delete from tiles where zoom_level = 1 and tile_column = 0;",
+        )
+        .unwrap();
+    let report = verify(&mbtiles).unwrap();
+    assert_eq!(report.mismatched, vec![TileCoord::new(1, 1, 1)]);
+    assert_eq!(report.unhashed, vec![TileCoord::new(2, 0, 3)]);
+    assert_eq!(report.missing, vec![TileCoord::new(1, 0, 0)]);
+    assert_eq!(
+        verify(tests.join("data/one_tile.mbtiles"))
+            .unwrap_err()
+            .kind,
+        ErrorKind::SchemaMissing
+    );
+    clear_data(output_name).unwrap();
+}
+
+#[test]
+fn export_into_existing_directory() {
+    let output_name = "export_into_existing_directory";
+    let (tests, output) = clear_data(output_name).unwrap();
+    let mbtiles = tests.join("data/one_tile.mbtiles");
+    let exported = output.join("exported");
+    let export_into = |existing: ExistingFiles| {
+        export_with_progress(
+            &mbtiles,
+            Some(&exported),
+            &ExportOptions {
+                grid_callback: "".to_owned(),
+                existing,
+                ..ExportOptions::default()
+            },
+            &TileFilter::default(),
+            &mut |_| {},
+        )
+    };
+    export_into(ExistingFiles::Fail).unwrap();
+    assert_eq!(
+        export_into(ExistingFiles::Fail).unwrap_err().kind,
+        ErrorKind::OutputExists
+    );
+    let tile = exported.join("0/0/0.png");
+    let original = fs::read(&tile).unwrap();
+
+    fs::write(&tile, b"edited").unwrap();
+    fs::remove_file(exported.join("1/0/0.png")).unwrap();
+    export_into(ExistingFiles::Skip).unwrap();
+    assert_eq!(fs::read(&tile).unwrap(), b"edited");
+    assert!(exported.join("1/0/0.png").exists());
+
+    export_into(ExistingFiles::UpdateChanged).unwrap();
+    assert_eq!(fs::read(&tile).unwrap(), original);
+
+    fs::write(&tile, b"edited").unwrap();
+    export_into(ExistingFiles::Overwrite).unwrap();
+    assert_eq!(fs::read(&tile).unwrap(), original);
+    clear_data(output_name).unwrap();
+}
+
+/// A writer whose reader went away, like a closed pipe.
+struct FailingWriter;
+
+impl std::io::Write for FailingWriter {
+    fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+        Err(std::io::ErrorKind::BrokenPipe.into())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn ndjson_export_and_import() {
+    let output_name = "ndjson_export_and_import";
+    let (tests, output) = clear_data(output_name).unwrap();
+    fs::create_dir_all(&output).unwrap();
+    let stream = output.join("tiles.ndjson");
+    export_with_progress(
+        tests.join("data/utf8grid.mbtiles"),
+        Some(stream.clone()),
+        &ExportOptions {
+            scheme: Scheme::Tms,
+            format: ExchangeFormat::Ndjson,
+            ..ExportOptions::default()
+        },
+        &TileFilter::default(),
+        &mut |_| {},
+    )
+    .unwrap();
+    let lines: Vec<serde_json::Value> = fs::read_to_string(&stream)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0]["metadata"]["name"], "utf8grid");
+    assert_eq!(lines[1]["type"], "tile");
+    assert_eq!(lines[2]["grid"]["data"]["100"]["ISO_A2"], "HR");
+    let err = export(
+        tests.join("data/utf8grid.mbtiles"),
+        Some(PathBuf::from("-")),
+        Scheme::Xyz,
+        ImageFormat::Png,
+        "".to_owned(),
+    )
+    .unwrap_err();
+    assert_eq!(err.kind, ErrorKind::InvalidInput);
+    assert!(!PathBuf::from("-").exists());
+    let mut sink = NdjsonSink::new(FailingWriter, Scheme::Xyz).unwrap();
+    let err = sink.write_metadata(&serde_json::Map::new()).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::Io);
+    for existing in [ExistingFiles::Skip, ExistingFiles::UpdateChanged] {
+        let err = export_with_progress(
+            tests.join("data/utf8grid.mbtiles"),
+            Some(stream.clone()),
+            &ExportOptions {
+                format: ExchangeFormat::Ndjson,
+                existing,
+                ..ExportOptions::default()
+            },
+            &TileFilter::default(),
+            &mut |_| {},
+        )
+        .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::InvalidInput);
+    }
+    export_with_progress(
+        tests.join("data/utf8grid.mbtiles"),
+        Some(stream.clone()),
+        &ExportOptions {
+            scheme: Scheme::Tms,
+            format: ExchangeFormat::Ndjson,
+            existing: ExistingFiles::Overwrite,
+            ..ExportOptions::default()
+        },
+        &TileFilter::default(),
+        &mut |_| {},
+    )
+    .unwrap();
+
+    let imported = output.join("imported.mbtiles");
+    let options = ImportOptions {
+        scheme: Scheme::Tms,
+        format: ExchangeFormat::Ndjson,
+        ..ImportOptions::default()
+    };
+    let report = import_with_options(stream.clone(), imported.clone(), &options).unwrap();
+    assert_eq!((report.tiles, report.grids), (1, 1));
+    assert_eq!(
+        checksum(&imported).unwrap().zooms,
+        checksum(tests.join("data/utf8grid.mbtiles")).unwrap().zooms
+    );
+    assert_eq!(
+        metadata_get(&imported, "name").unwrap().unwrap(),
+        "utf8grid"
+    );
+
+    fs::write(
+        &stream,
+        "{\"type\": \"tile\", \"z\": 0, \"x\": 0, \"y\": 0}\n",
+    )
+    .unwrap();
+    let err = import_with_options(stream, output.join("invalid.mbtiles"), &options).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::InvalidInput);
+    clear_data(output_name).unwrap();
+}
+
+#[test]
+fn export_grids_next_to_tiles_for_every_scheme() {
+    let output_name = "export_grids_next_to_tiles_for_every_scheme";
+    let (tests, output) = clear_data(output_name).unwrap();
+    for scheme in [Scheme::Xyz, Scheme::Tms, Scheme::Wms, Scheme::Ags] {
+        let exported = output.join(scheme.to_string());
+        export(
+            tests.join("data/utf8grid.mbtiles"),
+            Some(exported.clone()),
+            scheme,
+            ImageFormat::Png,
+            "".to_owned(),
+        )
+        .unwrap();
+        let mut files: Vec<PathBuf> = walkdir::WalkDir::new(&exported)
+            .into_iter()
+            .map(|entry| entry.unwrap().into_path())
+            .filter(|path| path.is_file() && !path.ends_with("metadata.json"))
+            .collect();
+        files.sort();
+        assert_eq!(files.len(), 2, "{} scheme", scheme);
+        let tile = files[1].to_str().unwrap();
+        assert!(tile.ends_with(".png"), "{} scheme", scheme);
+        assert_eq!(
+            files[0].to_str().unwrap(),
+            tile.replace(".png", ".grid.json"),
+            "{} scheme",
+            scheme
+        );
+    }
+    assert!(output
+        .join("ags/L00/R00000000/C00000000.grid.json")
+        .exists());
+
+    let options = ImportOptions {
+        scheme: Scheme::Ags,
+        ..ImportOptions::default()
+    };
+    let report =
+        import_with_options(output.join("ags"), output.join("ags.mbtiles"), &options).unwrap();
+    assert_eq!((report.tiles, report.grids), (1, 1));
+    clear_data(output_name).unwrap();
+}
+
+#[test]
+fn export_and_import_ags_tiles() {
+    let output_name = "export_and_import_ags_tiles";
+    let (_, output) = clear_data(output_name).unwrap();
+    fs::create_dir_all(&output).unwrap();
+    let tiles = [
+        (TileCoord::new(0, 0, 0), solid_png([0, 0, 0, 255])),
+        (TileCoord::new(4, 10, 3), solid_png([255, 0, 0, 255])),
+        (TileCoord::new(4, 3, 12), solid_png([0, 255, 0, 255])),
+    ];
+    let mbtiles = output.join("tiles.mbtiles");
+    let mut writer = MBTilesWriter::create(&mbtiles).unwrap();
+    for (coord, data) in tiles.iter() {
+        writer.put_tile(*coord, data).unwrap();
+    }
+    writer.finish().unwrap();
+
+    let exported = output.join("ags");
+    export(
+        mbtiles.clone(),
+        Some(exported.clone()),
+        Scheme::Ags,
+        ImageFormat::Png,
+        "".to_owned(),
+    )
+    .unwrap();
+    assert!(exported.join("L04/R00000003/C0000000a.png").exists());
+    assert!(exported.join("L04/R0000000c/C00000003.png").exists());
+
+    let imported = output.join("imported.mbtiles");
+    let options = ImportOptions {
+        scheme: Scheme::Ags,
+        ..ImportOptions::default()
+    };
+    let report = import_with_options(&exported, &imported, &options).unwrap();
+    assert_eq!(report.tiles, 3);
+    for (coord, data) in tiles.iter() {
+        assert_eq!(tile_get(&imported, *coord).unwrap().as_ref(), Some(data));
+    }
+    clear_data(output_name).unwrap();
+}
+
+#[test]
+fn export_multi_zoom_grids_with_their_data() {
+    let output_name = "export_multi_zoom_grids_with_their_data";
+    let (_, output) = clear_data(output_name).unwrap();
+    fs::create_dir_all(&output).unwrap();
+    let coords = [
+        TileCoord::new(0, 0, 0),
+        TileCoord::new(1, 0, 0),
+        TileCoord::new(1, 1, 1),
+        TileCoord::new(2, 1, 0),
+        TileCoord::new(2, 3, 2),
+    ];
+    assert_eq!(
+        TmsCoord::try_from(coords[3]).unwrap(),
+        TmsCoord::new(2, 1, 3)
+    );
+    assert_eq!(
+        TileCoord::try_from(TmsCoord::new(2, 1, 3)).unwrap(),
+        coords[3]
+    );
+    assert!(TileCoord::try_from(TmsCoord::new(2, 1, 4)).is_err());
+    let mbtiles = output.join("grids.mbtiles");
+    let mut writer = MBTilesWriter::create(&mbtiles).unwrap();
+    for coord in coords {
+        writer.put_tile(coord, &solid_png([0, 0, 0, 255])).unwrap();
+        let grid = utfgrid(&coord.to_string());
+        writer.put_grid(coord, &grid).unwrap();
+    }
+    writer.finish().unwrap();
+
+    let mut reader = MBTilesReader::open(&mbtiles).unwrap();
+    let mut grids = 0;
+    reader
+        .visit(&mut |entry| {
+            if let TileEntry::Grid(grid) = entry {
+                assert_eq!(grid.utfgrid["data"]["1"]["name"], grid.coord.to_string());
+                grids += 1;
+            }
+            Ok(())
+        })
+        .unwrap();
+    assert_eq!(grids, coords.len());
+
+    for scheme in [Scheme::Xyz, Scheme::Tms] {
+        let exported = output.join(scheme.to_string());
+        export(
+            mbtiles.clone(),
+            Some(exported.clone()),
+            scheme,
+            ImageFormat::Png,
+            "".to_owned(),
+        )
+        .unwrap();
+        for coord in coords {
+            let row = match scheme {
+                Scheme::Tms => coord.tms_y().unwrap(),
+                _ => coord.y,
+            };
+            let path = exported.join(format!("{}/{}/{}.grid.json", coord.zoom, coord.x, row));
+            let grid: serde_json::Value =
+                serde_json::from_reader(File::open(&path).unwrap()).unwrap();
+            assert_eq!(grid["data"]["1"]["name"], coord.to_string(), "{:?}", path);
+        }
+    }
+    clear_data(output_name).unwrap();
+}
+
+#[test]
+fn import_validates_and_repairs_grids() {
+    let output_name = "import_validates_and_repairs_grids";
+    let (_, output) = clear_data(output_name).unwrap();
+    let valid = utfgrid("one");
+    assert_eq!(validate_utfgrid(&valid).unwrap(), Vec::<String>::new());
+    let without_data =
+        serde_json::json!({"grid": ["!#", "  "], "keys": ["", "1", "2"], "data": {"1": {}}});
+    assert_eq!(validate_utfgrid(&without_data).unwrap(), vec!["2"]);
+    // a short row, a character after the last key and the data of an unknown key
+    let repairable = serde_json::json!({
+        "grid": ["!#", "!"],
+        "keys": ["", "1"],
+        "data": {"1": {"name": "one"}, "3": {}},
+    });
+    let err = validate_utfgrid(&repairable).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::CorruptGrid);
+    let repaired = repair_utfgrid(&repairable).unwrap();
+    assert_eq!(repaired["grid"], serde_json::json!(["! ", "! "]));
+    assert_eq!(repaired["data"], serde_json::json!({"1": {"name": "one"}}));
+    assert!(validate_utfgrid(&repaired).is_ok());
+    let broken = serde_json::json!({"grid": [" "]});
+    assert!(repair_utfgrid(&broken).is_err());
+    // an empty keys list gets the blank key
+    let repaired = repair_utfgrid(&serde_json::json!({"grid": ["!"], "keys": []})).unwrap();
+    assert_eq!(repaired["keys"], serde_json::json!([""]));
+    assert_eq!(repaired["grid"], serde_json::json!([" "]));
+    // an over-long row is truncated to the width of the others
+    let too_long = serde_json::json!({"grid": ["!!!", "!!!!!", "!!!"], "keys": ["", "1"]});
+    let repaired = repair_utfgrid(&too_long).unwrap();
+    assert_eq!(repaired["grid"], serde_json::json!(["!!!", "!!!", "!!!"]));
+    assert!(validate_utfgrid(&repaired).is_ok());
+
+    let tiles = output.join("tiles");
+    for (x, grid) in [&valid, &without_data, &repairable, &broken]
+        .iter()
+        .enumerate()
+    {
+        fs::create_dir_all(tiles.join(format!("2/{}", x))).unwrap();
+        fs::write(tiles.join(format!("2/{}/0.grid.json", x)), grid.to_string()).unwrap();
+    }
+    let check = |mode: GridMode, name: &str| {
+        let options = ImportOptions {
+            grids: mode,
+            ..ImportOptions::default()
+        };
+        import_with_options(tiles.clone(), output.join(name), &options).unwrap()
+    };
+    let (repairable_coord, broken_coord) = (TileCoord::new(2, 2, 0), TileCoord::new(2, 3, 0));
+
+    let report = check(GridMode::Keep, "keep.mbtiles");
+    assert_eq!(report.grids, 4);
+    let grids = &report.checked_grids;
+    let mut invalid: Vec<TileCoord> = grids.invalid.iter().map(|invalid| invalid.coord).collect();
+    // the files are read in directory order
+    invalid.sort();
+    assert_eq!(invalid, vec![repairable_coord, broken_coord]);
+    assert!(grids.rejected.is_empty() && grids.repaired.is_empty());
+    assert_eq!(
+        grids.missing_data.get(&TileCoord::new(2, 1, 0)),
+        Some(&vec!["2".to_owned()])
+    );
+
+    let mut report = check(GridMode::Reject, "reject.mbtiles");
+    assert_eq!(report.grids, 2);
+    report.checked_grids.rejected.sort();
+    assert_eq!(
+        report.checked_grids.rejected,
+        vec![repairable_coord, broken_coord]
+    );
+
+    let report = check(GridMode::Repair, "repair.mbtiles");
+    assert_eq!(report.grids, 3);
+    assert_eq!(report.checked_grids.repaired, vec![repairable_coord]);
+    assert_eq!(report.checked_grids.rejected, vec![broken_coord]);
+    let mut reader = MBTilesReader::open(output.join("repair.mbtiles")).unwrap();
+    reader
+        .visit(&mut |entry| {
+            if let TileEntry::Grid(grid) = entry {
+                assert!(validate_utfgrid(&grid.utfgrid).is_ok(), "{}", grid.coord);
+            }
+            Ok(())
+        })
+        .unwrap();
+
+    let options = ImportOptions {
+        grids: GridMode::Reject,
+        strict: true,
+        ..ImportOptions::default()
+    };
+    let err =
+        import_with_options(tiles.clone(), output.join("strict.mbtiles"), &options).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::CorruptGrid);
+    // the first invalid grid read, short rows or no keys
+    let source = std::error::Error::source(&err).expect("the validation error is kept");
+    let message = source.to_string();
+    assert!(
+        message.contains("columns instead of") || message == "no keys",
+        "{}",
+        message
+    );
+    clear_data(output_name).unwrap();
+}